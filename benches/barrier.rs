@@ -0,0 +1,31 @@
+//! Measures what clearing the stack after `with_ref` costs, compared to a
+//! plain borrow. Run with `cargo bench --bench barrier`.
+
+use std::{
+  hint::black_box,
+  time::{Duration, Instant},
+};
+
+use sensitive_data::SensitiveData;
+
+const ITERATIONS: u32 = 100_000;
+
+fn time(mut f: impl FnMut()) -> Duration {
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    f();
+  }
+  start.elapsed() / ITERATIONS
+}
+
+fn main() {
+  let data = SensitiveData::new_relaxed([1u8; 32]).unwrap();
+  println!("borrow:   {:?}",
+           time(|| {
+             black_box(data.borrow()[0]);
+           }));
+  println!("with_ref: {:?}",
+           time(|| {
+             black_box(data.with_ref(|value| value[0]));
+           }));
+}
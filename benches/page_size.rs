@@ -0,0 +1,35 @@
+//! Times asking for the page size, which is only asked of the OS once, and the
+//! allocations that need it. Run with `cargo bench --bench page_size`, under
+//! `strace -c` or `ltrace -c -e sysconf` to count the calls into the OS.
+
+use std::{
+  hint::black_box,
+  time::{Duration, Instant},
+};
+
+use sensitive_data::SensitiveData;
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+use sensitive_data::{MemoryProtector, OsProtector};
+
+const ITERATIONS: u32 = 10_000;
+
+fn time(mut f: impl FnMut()) -> Duration {
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    f();
+  }
+  start.elapsed() / ITERATIONS
+}
+
+fn main() {
+  // Only those targets ask the OS.
+  #[cfg(any(target_family = "unix", target_family = "windows"))]
+  println!("page_size: {:?} per call",
+           time(|| {
+             black_box(OsProtector.page_size());
+           }));
+  println!("new:       {:?} per allocation",
+           time(|| {
+             black_box(SensitiveData::new([1u8; 32]).unwrap());
+           }));
+}
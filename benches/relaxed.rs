@@ -0,0 +1,26 @@
+//! Compares borrowing a value protected between accesses with borrowing one in
+//! relaxed mode. Run with `cargo bench --bench relaxed`.
+
+use std::{
+  hint::black_box,
+  time::{Duration, Instant},
+};
+
+use sensitive_data::SensitiveData;
+
+const ITERATIONS: u32 = 100_000;
+
+fn time_borrows(data: &SensitiveData<[u8; 32]>) -> Duration {
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    black_box(data.borrow()[0]);
+  }
+  start.elapsed() / ITERATIONS
+}
+
+fn main() {
+  let protected = SensitiveData::new([1u8; 32]).unwrap();
+  let relaxed = SensitiveData::new_relaxed([1u8; 32]).unwrap();
+  println!("protected: {:?} per borrow", time_borrows(&protected));
+  println!("relaxed:   {:?} per borrow", time_borrows(&relaxed));
+}
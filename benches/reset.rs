@@ -0,0 +1,33 @@
+//! Compares reusing one allocation for a secret at a time through `reset_with`
+//! with allocating a new one for each. Run with `cargo bench --bench reset`.
+
+use std::{
+  hint::black_box,
+  time::{Duration, Instant},
+};
+
+use sensitive_data::SensitiveData;
+
+const ITERATIONS: u32 = 10_000;
+
+fn time(mut f: impl FnMut(u8)) -> Duration {
+  let start = Instant::now();
+  for i in 0..ITERATIONS {
+    f(i as u8);
+  }
+  start.elapsed() / ITERATIONS
+}
+
+fn main() {
+  let mut data = SensitiveData::new([0u8; 32]).unwrap();
+  println!("reset_with: {:?} per secret",
+           time(|i| {
+             data.reset_with([i; 32]).unwrap();
+             black_box(data.borrow()[0]);
+           }));
+  println!("new:        {:?} per secret",
+           time(|i| {
+             let data = SensitiveData::new([i; 32]).unwrap();
+             black_box(data.borrow()[0]);
+           }));
+}
@@ -0,0 +1,328 @@
+use alloc::vec::Vec;
+use core::{
+  cell::{Cell, RefCell},
+  fmt,
+  marker::PhantomData,
+  mem::{align_of, size_of, ManuallyDrop},
+};
+
+use super::{drop_failed, err, zeroize, Error, Protection, SensitiveBytes};
+
+/// The alignment of every slot, which is enough for any primitive.
+const SLOT_ALIGN: usize = 16;
+
+/// Many small secrets sharing one locked region, since locking has page
+/// granularity and a page per 32 byte key adds up quickly. The region is split
+/// into equally sized slots, each handed out as a `SensitiveHandle` and
+/// zeroized as soon as the handle is dropped.
+///
+/// The protection is per arena rather than per handle, so while any handle is
+/// accessed the whole region is accessible.
+#[derive(Debug)]
+pub struct SensitiveArena {
+  region: SensitiveBytes,
+  /// The distance between slots, a multiple of `SLOT_ALIGN`.
+  slot_size: usize,
+  /// The slots not handed out.
+  free: RefCell<Vec<usize>>,
+  /// The number of handles currently being read, or written.
+  readers: Cell<usize>,
+  writers: Cell<usize>,
+}
+
+impl SensitiveArena {
+  /// Creates an arena with at least `slots` slots of at least `slot_size`
+  /// bytes each. The region is rounded up to whole pages, and the slots fill
+  /// all of it.
+  pub fn new(slot_size: usize, slots: usize) -> Result<Self, Error> {
+    let slot_size = slot_size.max(1).saturating_add(SLOT_ALIGN - 1) / SLOT_ALIGN * SLOT_ALIGN;
+    // Too much to allocate either way, so saturating makes the layout fail.
+    let region = SensitiveBytes::with_len(slot_size.saturating_mul(slots))?;
+    let free = (0..region.locked_size() / slot_size).rev().collect();
+    Ok(SensitiveArena { region,
+                        slot_size,
+                        free: RefCell::new(free),
+                        readers: Cell::new(0),
+                        writers: Cell::new(0) })
+  }
+
+  /// Moves `value` into a free slot. Fails with `Error::ArenaFull` if there is
+  /// none, or with the error if the region can't be made writable, in which
+  /// case the slot stays free. Panics if `T` doesn't fit in a slot.
+  pub fn insert<T>(&self, value: T) -> Result<SensitiveHandle<'_, T>, Error> {
+    assert!(size_of::<T>() <= self.slot_size && align_of::<T>() <= SLOT_ALIGN,
+            "The value doesn't fit in a slot of the SensitiveArena");
+    let slot = self.free.borrow_mut().pop().ok_or(Error::ArenaFull)?;
+    let handle = SensitiveHandle::<T> { arena: self,
+                                        slot,
+                                        _value: PhantomData };
+    // Nothing is in the slot to drop if making it writable fails.
+    let handle = ManuallyDrop::new(handle);
+    if let Err(e) = self.try_access(Protection::Writable, || unsafe {
+                          handle.ptr().write(value)
+                        })
+    {
+      self.free.borrow_mut().push(slot);
+      return Err(e.into());
+    }
+    Ok(ManuallyDrop::into_inner(handle))
+  }
+
+  /// Allocates the default value of `T`, such as zeroes for a key.
+  pub fn alloc<T: Default>(&self) -> Result<SensitiveHandle<'_, T>, Error> {
+    self.insert(T::default())
+  }
+
+  /// The number of slots in the arena.
+  #[inline(always)]
+  pub fn capacity(&self) -> usize {
+    self.region.locked_size() / self.slot_size
+  }
+
+  /// The number of slots not handed out.
+  #[inline(always)]
+  pub fn available(&self) -> usize {
+    self.free.borrow().len()
+  }
+
+  #[inline(always)]
+  pub fn slot_size(&self) -> usize {
+    self.slot_size
+  }
+
+  #[inline(always)]
+  pub fn is_locked(&self) -> bool {
+    self.region.is_locked()
+  }
+
+  /// The size of the region, which is shared by every slot.
+  #[inline(always)]
+  pub fn locked_size(&self) -> usize {
+    self.region.locked_size()
+  }
+
+  /// Runs `f` with the region at least as accessible as `protection`, and
+  /// restores it afterwards, even if `f` panics.
+  fn access<R>(&self, protection: Protection, f: impl FnOnce() -> R) -> R {
+    self.try_access(protection, f)
+        .expect("Could not make SensitiveArena accessible")
+  }
+
+  /// Like `access`, but hands back the error if the region couldn't be made
+  /// accessible, in which case `f` isn't run.
+  fn try_access<R>(&self,
+                   protection: Protection,
+                   f: impl FnOnce() -> R)
+                   -> Result<R, err::IoError> {
+    /// Unregisters the access when dropped.
+    struct Access<'a> {
+      arena: &'a SensitiveArena,
+      counter: &'a Cell<usize>,
+    }
+
+    impl Drop for Access<'_> {
+      fn drop(&mut self) {
+        self.counter.set(self.counter.get() - 1);
+        if let Err(e) = self.arena.update_protection() {
+          drop_failed("Could not make SensitiveArena inaccessible", e);
+        }
+      }
+    }
+
+    let counter = if protection == Protection::Writable {
+      &self.writers
+    } else {
+      &self.readers
+    };
+    counter.set(counter.get() + 1);
+    if let Err(e) = self.update_protection() {
+      // The protection it had is still what the other accesses need.
+      counter.set(counter.get() - 1);
+      return Err(e);
+    }
+    let access = Access { arena: self,
+                          counter };
+    let result = f();
+    drop(access);
+    Ok(result)
+  }
+
+  /// Makes the region as accessible as the handles currently accessed need.
+  fn update_protection(&self) -> Result<(), err::IoError> {
+    if self.writers.get() > 0 {
+      self.region
+          .protect(Protection::Writable, |protector, ptr, len| {
+            protector.make_writable(ptr, len)
+          })
+    } else if self.readers.get() > 0 {
+      self.region.make_readable()
+    } else {
+      self.region.make_idle()
+    }
+  }
+}
+
+/// A value in a slot of a `SensitiveArena`. Dropping it drops the value and
+/// zeroizes the slot, which is then free to be reused.
+pub struct SensitiveHandle<'arena, T> {
+  arena: &'arena SensitiveArena,
+  slot: usize,
+  _value: PhantomData<T>,
+}
+
+impl<T> SensitiveHandle<'_, T> {
+  #[inline(always)]
+  fn ptr(&self) -> *mut T {
+    unsafe {
+      (self.arena.region.inner_ptr as *mut u8).add(self.slot * self.arena.slot_size) as *mut T
+    }
+  }
+
+  /// Runs `f` with the value readable, and makes the arena inaccessible again
+  /// before returning, unless other handles are still accessed.
+  pub fn with_ref<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    self.arena
+        .access(Protection::Readable, || f(unsafe { &*self.ptr() }))
+  }
+
+  /// Runs `f` with the value writable, and makes the arena inaccessible again
+  /// before returning, unless other handles are still accessed.
+  pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+    self.arena
+        .access(Protection::Writable, || f(unsafe { &mut *self.ptr() }))
+  }
+}
+
+impl<T> Drop for SensitiveHandle<'_, T> {
+  fn drop(&mut self) {
+    let cleared = self.arena.try_access(Protection::Writable, || unsafe {
+                              core::ptr::drop_in_place(self.ptr());
+                              zeroize(core::slice::from_raw_parts_mut(self.ptr() as *mut u8,
+                                                                      self.arena.slot_size));
+                            });
+    match cleared {
+      Ok(()) => self.arena.free.borrow_mut().push(self.slot),
+      // The slot still holds the value, so it isn't reused. The region is
+      // cleared as a whole once the arena is dropped.
+      Err(e) => drop_failed("Could not make SensitiveArena writable", e),
+    }
+  }
+}
+
+// Never touches the value, so it can't leak it.
+impl<T> fmt::Debug for SensitiveHandle<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SensitiveHandle")
+     .field("slot", &self.slot)
+     .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(target_family = "unix")]
+  use crate::tests::is_inaccessible;
+  use crate::tests::{page_size, CountsDrops};
+
+  #[test]
+  fn many_keys_share_a_page() {
+    let arena = SensitiveArena::new(32, 100).unwrap();
+    assert_eq!(arena.locked_size(), page_size());
+    assert_eq!(arena.capacity(), page_size() / 32);
+    assert!(arena.is_locked());
+    let mut keys: Vec<_> = (0..100).map(|_| arena.alloc::<[u8; 32]>().unwrap())
+                                   .collect();
+    assert_eq!(arena.available(), arena.capacity() - 100);
+    for (i, key) in keys.iter_mut().enumerate() {
+      key.with_mut(|key| key.fill(i as u8));
+    }
+    for (i, key) in keys.iter().enumerate() {
+      key.with_ref(|key| assert_eq!(key, &[i as u8; 32]));
+    }
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&arena.region));
+    drop(keys);
+    assert_eq!(arena.available(), arena.capacity());
+  }
+
+  #[test]
+  fn full() {
+    let arena = SensitiveArena::new(page_size(), 1).unwrap();
+    let first = arena.insert(1u64).unwrap();
+    assert!(matches!(arena.insert(2u64), Err(Error::ArenaFull)));
+    drop(first);
+    let second = arena.insert(3u64).unwrap();
+    second.with_ref(|value| assert_eq!(*value, 3));
+  }
+
+  #[test]
+  fn zeroized_per_handle() {
+    let arena = SensitiveArena::new(16, 2).unwrap();
+    let first = arena.insert([0xffu8; 16]).unwrap();
+    let second = arena.insert([0xeeu8; 16]).unwrap();
+    let first_slot = first.slot * arena.slot_size;
+    let second_slot = second.slot * arena.slot_size;
+    drop(first);
+    arena.region.with_ref(|region| {
+                  assert!(region[first_slot..first_slot + 16].iter().all(|b| *b == 0));
+                  assert!(region[second_slot..second_slot + 16].iter()
+                                                               .all(|b| *b == 0xee));
+                });
+    drop(second);
+    assert!(arena.region
+                 .with_ref(|region| region.iter().all(|b| *b == 0)));
+  }
+
+  #[test]
+  fn drops_values() {
+    let mut drops = 0;
+    let arena = SensitiveArena::new(size_of::<CountsDrops>(), 1).unwrap();
+    let handle = arena.insert(CountsDrops { drops: &mut drops }).unwrap();
+    drop(handle);
+    assert_eq!(drops, 1);
+  }
+
+  #[test]
+  fn nested_access() {
+    let arena = SensitiveArena::new(8, 2).unwrap();
+    let mut first = arena.insert(1u64).unwrap();
+    let second = arena.insert(2u64).unwrap();
+    second.with_ref(|second| first.with_mut(|first| *first += *second));
+    first.with_ref(|first| assert_eq!(*first, 3));
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&arena.region));
+  }
+
+  #[test]
+  #[should_panic(expected = "The value doesn't fit in a slot of the SensitiveArena")]
+  fn too_large() {
+    let arena = SensitiveArena::new(16, 1).unwrap();
+    let _ = arena.insert([0u8; 17]);
+  }
+
+  #[cfg(all(not(miri), any(target_family = "unix", target_family = "windows")))]
+  #[test]
+  fn slot_kept_when_not_made_writable() {
+    use crate::{
+      protector::tests::{Call, Mock},
+      OsProtector,
+    };
+    static FAILS_TO_WRITE: Mock = Mock::wrapping(&OsProtector);
+    let mut arena = SensitiveArena::new(32, 4).unwrap();
+    let available = arena.available();
+    arena.region.protector = &FAILS_TO_WRITE;
+    FAILS_TO_WRITE.fail(Call::Writable);
+    assert!(matches!(arena.insert([1u8; 32]), Err(Error::IoError(_))));
+    assert_eq!(arena.available(), available);
+    arena.region.protector = &OsProtector;
+    let key = arena.insert([1u8; 32]).unwrap();
+    assert_eq!(key.with_ref(|key| key[0]), 1);
+    assert_eq!(arena.available(), available - 1);
+  }
+
+  #[test]
+  fn too_many() {
+    assert!(matches!(SensitiveArena::new(32, usize::MAX), Err(Error::TooLarge)));
+  }
+}
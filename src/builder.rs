@@ -0,0 +1,216 @@
+#[cfg(feature = "allocator-api")]
+use core::alloc::Allocator;
+use core::{alloc::Layout, marker::PhantomData};
+
+#[cfg(feature = "getrandom")]
+use super::WipePolicy;
+use super::{Error, Locking, MemoryProtector, Options, SensitiveData};
+
+/// Configures a `SensitiveData` in one place, for the combinations of options
+/// that the `new_*` constructors don't cover. Everything defaults to what `new`
+/// does.
+///
+/// ```
+/// # use sensitive_data::SensitiveDataBuilder;
+/// let key = SensitiveDataBuilder::new().guard_pages(true)
+///                                      .wipe_on_fork(true)
+///                                      .build([0u8; 32])
+///                                      .unwrap();
+/// ```
+pub struct SensitiveDataBuilder<T> {
+  options: Options,
+  #[cfg(feature = "getrandom")]
+  wipe: WipePolicy,
+  _value: PhantomData<fn(T)>,
+}
+
+impl<T> SensitiveDataBuilder<T> {
+  pub fn new() -> Self {
+    SensitiveDataBuilder { options: Options::default(),
+                           #[cfg(feature = "getrandom")]
+                           wipe: WipePolicy::default(),
+                           _value: PhantomData }
+  }
+
+  /// Whether the memory is locked, like `new` or `new_unlocked`.
+  pub fn locked(mut self, locked: bool) -> Self {
+    self.options.locking = if locked {
+      Locking::Required
+    } else {
+      Locking::Disabled
+    };
+    self
+  }
+
+  /// Whether the memory is surrounded by inaccessible pages, so that overruns
+  /// fault instead of touching the value. Defaults to whether the
+  /// `guard-pages` feature is enabled.
+  pub fn guard_pages(mut self, guard_pages: bool) -> Self {
+    self.options.guard_pages = guard_pages;
+    self
+  }
+
+  /// Whether the memory is kept out of core dumps, where supported.
+  pub fn exclude_from_dump(mut self, exclude: bool) -> Self {
+    self.options.exclude_from_dump = exclude;
+    self
+  }
+
+  /// Whether forked children get zeroes in place of the value, like
+  /// `new_wipe_on_fork`.
+  pub fn wipe_on_fork(mut self, wipe: bool) -> Self {
+    self.options.wipe_on_fork = wipe;
+    self
+  }
+
+  /// What's written over the memory before it's freed, like
+  /// `new_with_wipe_policy`.
+  #[cfg(feature = "getrandom")]
+  pub fn wipe_policy(mut self, policy: WipePolicy) -> Self {
+    self.wipe = policy;
+    self
+  }
+
+  /// Aligns the memory to `align` if that's more than a page, like
+  /// `new_aligned`. `build` fails unless it's a power of two.
+  pub fn align(mut self, align: usize) -> Self {
+    self.options.align = align;
+    self
+  }
+
+  /// Protects the memory with `protector`, like `with_protector`.
+  pub fn protector(mut self, protector: &'static dyn MemoryProtector) -> Self {
+    self.options.protector = protector;
+    self
+  }
+
+  /// Allocates the memory from `allocator` instead of the global allocator,
+  /// such as from a pool of memory that's already locked. The memory is still
+  /// protected, and locked unless `locked(false)`, like any other.
+  #[cfg(feature = "allocator-api")]
+  pub fn allocator(mut self, allocator: &'static (dyn Allocator + Sync)) -> Self {
+    self.options.allocator = allocator;
+    self
+  }
+
+  pub fn build(self, t: T) -> Result<SensitiveData<T>, Error> {
+    Layout::from_size_align(0, self.options.align)?;
+    #[allow(unused_mut)]
+    let mut data = SensitiveData::new_with_options(t, self.options)?;
+    #[cfg(feature = "getrandom")]
+    {
+      data.wipe = self.wipe;
+    }
+    Ok(data)
+  }
+}
+
+impl<T> Default for SensitiveDataBuilder<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tests::page_size;
+
+  #[test]
+  fn defaults() {
+    let a = SensitiveDataBuilder::new().build([1u8; 32]).unwrap();
+    assert!(a.is_locked());
+    assert_eq!(a.guard_pages, cfg!(feature = "guard-pages"));
+    assert!(a.dump_excluded);
+    assert!(!a.wipe_on_fork);
+    assert_eq!(a.memory_layout.align(), page_size());
+  }
+
+  #[test]
+  fn combined() {
+    let a = SensitiveDataBuilder::new().locked(false)
+                                       .guard_pages(true)
+                                       .exclude_from_dump(false)
+                                       .wipe_on_fork(true)
+                                       .align(1 << 21)
+                                       .build([1u8; 32])
+                                       .unwrap();
+    assert!(!a.is_locked());
+    assert!(a.wipe_on_fork);
+    assert_eq!(a.inner_ptr as usize % (1 << 21), 0);
+    assert_eq!(*a.borrow(), [1; 32]);
+    #[cfg(all(target_os = "linux", not(miri)))]
+    {
+      use crate::tests::{is_page_inaccessible, vm_flags};
+      let ptr = a.inner_ptr as usize;
+      assert!(is_page_inaccessible(ptr - page_size()));
+      assert!(is_page_inaccessible(ptr + a.locked_size()));
+      assert!(!vm_flags(ptr).iter()
+                            .any(|flag| flag == "dd" || flag == "lo"));
+    }
+  }
+
+  #[test]
+  fn without_guard_pages() {
+    let a = SensitiveDataBuilder::new().guard_pages(false)
+                                       .build([1u8; 32])
+                                       .unwrap();
+    assert!(!a.guard_pages);
+    assert_eq!(*a.borrow(), [1; 32]);
+  }
+
+  #[cfg(feature = "getrandom")]
+  #[test]
+  fn wipe_policy() {
+    let a = SensitiveDataBuilder::new().wipe_policy(WipePolicy::NPass(3))
+                                       .build([1u8; 32])
+                                       .unwrap();
+    assert_eq!(a.wipe, WipePolicy::NPass(3));
+  }
+
+  #[cfg(feature = "allocator-api")]
+  #[test]
+  fn custom_allocator() {
+    use core::{
+      alloc::AllocError,
+      ptr::NonNull,
+      sync::atomic::{AtomicUsize, Ordering},
+    };
+    use std::alloc::System;
+
+    struct Counting {
+      allocated: AtomicUsize,
+      deallocated: AtomicUsize,
+    }
+
+    unsafe impl Allocator for Counting {
+      fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocated.fetch_add(1, Ordering::Relaxed);
+        System.allocate(layout)
+      }
+
+      unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.deallocated.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.deallocate(ptr, layout) }
+      }
+    }
+
+    static COUNTING: Counting = Counting { allocated: AtomicUsize::new(0),
+                                           deallocated: AtomicUsize::new(0) };
+    let a = SensitiveDataBuilder::new().allocator(&COUNTING)
+                                       .guard_pages(true)
+                                       .build([1u8; 32])
+                                       .unwrap();
+    assert_eq!(*a.borrow(), [1; 32]);
+    let b = a.clone();
+    assert_eq!(COUNTING.allocated.load(Ordering::Relaxed), 2);
+    drop((a, b));
+    assert_eq!(COUNTING.deallocated.load(Ordering::Relaxed), 2);
+  }
+
+  #[test]
+  fn invalid_alignment() {
+    assert!(matches!(SensitiveDataBuilder::new().align(3).build([1u8; 32]),
+                     Err(Error::LayoutError(_))));
+  }
+}
@@ -0,0 +1,460 @@
+use alloc::boxed::Box;
+use core::{convert::TryFrom, ptr::slice_from_raw_parts_mut};
+#[cfg(feature = "std")]
+use std::{
+  fs::File,
+  io::{self, Read, Write},
+  path::Path,
+};
+
+use super::{
+  ct, zeroize, zeroize_stack, CtBool, Error, HolderInner, Options, SensitiveData, SensitiveSlice,
+};
+
+/// Sensitive bytes with a length only known at runtime.
+pub type SensitiveBytes = SensitiveSlice<u8>;
+
+impl SensitiveData<[u8]> {
+  /// Creates `len` zeroed bytes, which are only locked as they're first
+  /// touched, where supported. That saves locking the whole buffer up front
+  /// when it's large but filled a little at a time. The zeroes come from the
+  /// allocator, so how many pages are touched right away is up to it.
+  pub fn with_len_lock_on_fault(len: usize) -> Result<Self, Error> {
+    let options = Options { lock_on_fault: true,
+                            ..Options::default() };
+    let holder =
+      Self::allocate_holder(Self::layout(len, options.protector.page_size())?,
+                            len,
+                            options,
+                            |ptr| slice_from_raw_parts_mut(ptr, len) as *mut HolderInner<[u8]>)?;
+    holder.make_inaccessible()
+          .expect("Could not make the new SensitiveData inaccessible");
+    Ok(holder)
+  }
+
+  /// Creates a copy of `bytes`. The source is left untouched, so it's up to the
+  /// caller to clear it.
+  pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+    let mut holder = Self::with_len(bytes.len())?;
+    holder.borrow_mut().copy_from_slice(bytes);
+    Ok(holder)
+  }
+
+  /// Decodes `hex` straight into the locked memory. Upper and lower case are
+  /// both accepted. Fails with `Error::InvalidEncoding` if the length is odd,
+  /// or if anything but hex digits is found, in which case whatever was decoded
+  /// is zeroized.
+  pub fn from_hex(hex: &str) -> Result<Self, Error> {
+    if !hex.len().is_multiple_of(2) {
+      return Err(Error::InvalidEncoding);
+    }
+    let mut holder = Self::with_len(hex.len() / 2)?;
+    let valid = ct::decode_hex(hex.as_bytes(), &mut holder.try_borrow_mut()?);
+    zeroize_stack();
+    if valid {
+      Ok(holder)
+    } else {
+      Err(Error::InvalidEncoding)
+    }
+  }
+
+  /// Decodes padded standard base64 straight into the locked memory, like
+  /// `from_hex`.
+  #[cfg(feature = "base64")]
+  pub fn from_base64(encoded: &str) -> Result<Self, Error> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let unpadded = encoded.trim_end_matches('=').len();
+    let mut holder = Self::with_len(unpadded / 4 * 3 + unpadded % 4 * 3 / 4)?;
+    let decoded = STANDARD.decode_slice(encoded, &mut holder.try_borrow_mut()?);
+    // The decoder works through the input in chunks on the stack.
+    zeroize_stack();
+    match decoded {
+      Ok(len) if len == holder.len() => Ok(holder),
+      _ => Err(Error::InvalidEncoding),
+    }
+  }
+
+  /// Reads exactly `len` bytes from `reader` straight into the locked memory,
+  /// so that they never pass through an unprotected buffer on the way. Any
+  /// buffering done by `reader` itself is out of our hands, though.
+  #[cfg(feature = "std")]
+  pub fn read_from(reader: &mut impl Read, len: usize) -> Result<Self, Error> {
+    let mut holder = Self::with_len(len)?;
+    reader.read_exact(&mut holder.try_borrow_mut()?)?;
+    Ok(holder)
+  }
+
+  /// Reads exactly `len` bytes from the start of the file at `path`, like
+  /// `read_from`. The file is unbuffered, so the bytes are read directly into
+  /// the locked memory.
+  #[cfg(feature = "std")]
+  pub fn from_file_exact(path: impl AsRef<Path>, len: usize) -> Result<Self, Error> {
+    Self::read_from(&mut File::open(path)?, len)
+  }
+
+  /// Writes the bytes to `dest`, such as a cipher, and zeroizes them as they
+  /// go, a chunk at a time, so that less and less of the plaintext is left
+  /// in memory. The buffer is all zeroes afterwards, but keeps its length. If
+  /// `dest` fails, only what it accepted is zeroized, down to the byte.
+  #[cfg(feature = "std")]
+  pub fn drain_into(&mut self, dest: &mut impl Write) -> Result<(), Error> {
+    const CHUNK: usize = 64;
+    let mut bytes = self.try_borrow_mut()?;
+    let mut written = 0;
+    while written < bytes.len() {
+      let end = bytes.len().min(written + CHUNK);
+      // Like `write_all`, but zeroizing whatever `dest` took, even if it fails
+      // partway through a chunk.
+      match dest.write(&bytes[written..end]) {
+        Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+        Ok(n) => {
+          zeroize(&mut bytes[written..written + n]);
+          written += n;
+        }
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => return Err(e.into()),
+      }
+    }
+    Ok(())
+  }
+
+  /// Splits the bytes at `mid` into two independent secrets, copying each half
+  /// straight from this one's locked memory into the new ones. This one is
+  /// zeroized and freed afterwards, or when either half can't be created.
+  /// Panics if `mid` is greater than the length, like `slice::split_at`.
+  pub fn split_at(self, mid: usize) -> Result<(Self, Self), Error> {
+    assert!(mid <= self.len(),
+            "mid {} out of bounds for {} bytes",
+            mid,
+            self.len());
+    let mut head = Self::with_len(mid)?;
+    let mut tail = Self::with_len(self.len() - mid)?;
+    {
+      let bytes = self.try_borrow()?;
+      let (head_bytes, tail_bytes) = bytes.split_at(mid);
+      head.try_borrow_mut()?.copy_from_slice(head_bytes);
+      tail.try_borrow_mut()?.copy_from_slice(tail_bytes);
+    }
+    Ok((head, tail))
+  }
+
+  /// Copies the bytes into a regular `Box`, for APIs that insist on one, and
+  /// zeroizes and frees the locked memory. Panics like `borrow` if the bytes
+  /// can't be read.
+  ///
+  /// # Safety
+  /// The box has none of the protection. It may be swapped out, shows up in
+  /// core dumps, and isn't zeroized when it's dropped, so the bytes may linger
+  /// on the heap for as long as the process lives.
+  pub unsafe fn into_unprotected_box(self) -> Box<[u8]> {
+    Box::from(&*self.borrow())
+  }
+}
+
+impl<const N: usize> SensitiveData<[u8; N]> {
+  /// Copies `a` if `condition` is set, and `b` otherwise, into a new secret,
+  /// without branching on `condition`. Both are readable for the duration, and
+  /// every byte of both is read either way.
+  pub fn ct_select(condition: CtBool, a: &Self, b: &Self) -> Result<Self, Error> {
+    let mut selected = SensitiveData::new([0; N])?;
+    {
+      let (a, b) = (a.try_borrow()?, b.try_borrow()?);
+      ct::select(condition, &*a, &*b, &mut *selected.try_borrow_mut()?);
+    }
+    zeroize_stack();
+    Ok(selected)
+  }
+}
+
+impl<const N: usize> TryFrom<[u8; N]> for SensitiveData<[u8; N]> {
+  type Error = Error;
+
+  /// Moves the bytes in, and zeroizes what's left behind of the argument. The
+  /// caller's own copy is out of reach, so prefer `&mut [u8; N]` if there is
+  /// one.
+  fn try_from(mut bytes: [u8; N]) -> Result<Self, Error> {
+    let data = SensitiveData::new(bytes);
+    zeroize(&mut bytes);
+    data
+  }
+}
+
+impl<const N: usize> TryFrom<&mut [u8; N]> for SensitiveData<[u8; N]> {
+  type Error = Error;
+
+  /// Copies the bytes in, and zeroizes the source.
+  fn try_from(bytes: &mut [u8; N]) -> Result<Self, Error> {
+    let data = SensitiveData::new(*bytes);
+    zeroize(bytes);
+    data
+  }
+}
+
+impl TryFrom<&[u8]> for SensitiveData<[u8]> {
+  type Error = Error;
+
+  /// Like `from_slice`.
+  fn try_from(bytes: &[u8]) -> Result<Self, Error> {
+    Self::from_slice(bytes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tests::page_size;
+  #[cfg(all(target_os = "linux", not(miri)))]
+  use crate::tests::vm_flags;
+
+  #[test]
+  fn split() {
+    let original: Vec<u8> = (0..100).collect();
+    let a = SensitiveBytes::from_slice(&original).unwrap();
+    let address = a.inner_ptr as *mut u8 as usize;
+    let (head, tail) = a.split_at(30).unwrap();
+    assert!(crate::tests::freed_zeroized(address));
+    assert_eq!((head.len(), tail.len()), (30, 70));
+    assert_eq!([&head.borrow()[..], &tail.borrow()[..]].concat(), original);
+    let (empty, all) = SensitiveBytes::from_slice(&original).unwrap()
+                                                            .split_at(0)
+                                                            .unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(*all.borrow(), original[..]);
+  }
+
+  #[test]
+  fn unprotected_box() {
+    let a = SensitiveBytes::from_slice(b"secret").unwrap();
+    let address = a.inner_ptr as *mut u8 as usize;
+    let boxed = unsafe { a.into_unprotected_box() };
+    assert_eq!(&*boxed, b"secret");
+    assert!(crate::tests::freed_zeroized(address));
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn drained() {
+    let bytes: Vec<u8> = (0..200).collect();
+    let mut a = SensitiveBytes::from_slice(&bytes).unwrap();
+    let mut sink = Vec::new();
+    a.drain_into(&mut sink).unwrap();
+    assert_eq!(sink, bytes);
+    assert_eq!(a.len(), 200);
+    assert!(a.borrow().iter().all(|b| *b == 0));
+    #[cfg(target_family = "unix")]
+    assert!(crate::tests::is_inaccessible(&a));
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn drained_until_write_fails() {
+    let mut a = SensitiveBytes::from_slice(&[1; 200]).unwrap();
+    let mut sink = [0u8; 100];
+    assert!(matches!(a.drain_into(&mut &mut sink[..]), Err(Error::IoError(_))));
+    assert_eq!(sink, [1; 100]);
+    // The first chunk went out in full, and the second one in part, which is
+    // zeroized as far as it went.
+    let left = a.borrow();
+    assert!(left[..100].iter().all(|b| *b == 0));
+    assert!(left[100..].iter().all(|b| *b == 1));
+  }
+
+  #[test]
+  fn zeroized_when_created() {
+    let a = SensitiveBytes::with_len(100).unwrap();
+    assert_eq!(a.len(), 100);
+    assert!(a.borrow().iter().all(|b| *b == 0));
+  }
+
+  #[cfg(all(target_os = "linux", not(miri)))]
+  #[test]
+  fn locked_on_fault() {
+    let mut a = SensitiveBytes::with_len_lock_on_fault(16 * page_size()).unwrap();
+    assert!(a.is_locked());
+    assert!(a.borrow().iter().all(|b| *b == 0));
+    a.borrow_mut()[page_size()] = 1;
+    let flags = vm_flags(a.inner_ptr as *mut u8 as usize);
+    assert!(flags.iter().any(|flag| flag == "lf"), "{:?}", flags);
+  }
+
+  /// Kernels without `mlock2` get plain `mlock`.
+  #[cfg(all(target_os = "linux", not(miri)))]
+  #[test]
+  fn locked_on_fault_without_mlock2() {
+    use crate::sys::FAIL_MLOCK2;
+    FAIL_MLOCK2.with(|failure| failure.set(Some(libc::ENOSYS)));
+    let a = SensitiveBytes::with_len_lock_on_fault(16 * page_size());
+    FAIL_MLOCK2.with(|failure| failure.set(None));
+    let a = a.unwrap();
+    assert!(a.is_locked());
+    let flags = vm_flags(a.inner_ptr as *mut u8 as usize);
+    assert!(flags.iter().any(|flag| flag == "lo"), "{:?}", flags);
+    assert!(!flags.iter().any(|flag| flag == "lf"), "{:?}", flags);
+  }
+
+  /// Fills `len` bytes with a pattern and reads it back, checking that every
+  /// page is readable during the borrow and inaccessible after.
+  #[cfg(all(target_family = "unix", not(miri)))]
+  fn round_trip(len: usize) {
+    use crate::tests::{inaccessible_pages, is_inaccessible};
+    let mut a = SensitiveBytes::with_len(len).unwrap();
+    assert_eq!(a.memory_layout.size(),
+               len.div_ceil(page_size()) * page_size());
+    a.with_mut(|bytes| {
+       for (i, byte) in bytes.iter_mut().enumerate() {
+         *byte = i as u8 ^ 0x5a;
+       }
+     });
+    assert!(is_inaccessible(&a));
+    a.with_ref(|bytes| {
+       assert_eq!(bytes.len(), len);
+       assert!(bytes.iter()
+                    .enumerate()
+                    .all(|(i, byte)| *byte == i as u8 ^ 0x5a));
+       assert!(inaccessible_pages(&a).iter()
+                                     .all(|inaccessible| !inaccessible));
+     });
+    assert!(is_inaccessible(&a));
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn page_boundaries() {
+    for len in [1,
+                page_size() - 1,
+                page_size(),
+                page_size() + 1,
+                3 * page_size()]
+    {
+      round_trip(len);
+    }
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  proptest::proptest! {
+    #[test]
+    fn any_length(len in 1..4 * page_size()) {
+      round_trip(len);
+    }
+  }
+
+  #[test]
+  fn selected() {
+    let a = SensitiveData::new([1u8; 100]).unwrap();
+    let b = SensitiveData::new([2u8; 100]).unwrap();
+    assert_eq!(*SensitiveData::ct_select(true.into(), &a, &b).unwrap()
+                                                             .borrow(),
+               [1; 100]);
+    assert_eq!(*SensitiveData::ct_select(false.into(), &a, &b).unwrap()
+                                                              .borrow(),
+               [2; 100]);
+  }
+
+  #[test]
+  fn copied_from_slice() {
+    let a = SensitiveBytes::from_slice(b"secret").unwrap();
+    assert_eq!(&*a.borrow(), b"secret");
+  }
+
+  #[test]
+  fn spans_multiple_pages() {
+    let len = page_size() + 1;
+    let mut a = SensitiveBytes::with_len(len).unwrap();
+    assert_eq!(a.memory_layout.size(), 2 * page_size());
+    a.borrow_mut()[len - 1] = 1;
+    assert_eq!(a.borrow()[len - 1], 1);
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn read_from_reader() {
+    let mut reader = std::io::Cursor::new(b"secret and more".to_vec());
+    let a = SensitiveBytes::read_from(&mut reader, 6).unwrap();
+    assert_eq!(&*a.borrow(), b"secret");
+    assert_eq!(reader.position(), 6);
+    let mut reader = std::io::Cursor::new(b"short".to_vec());
+    assert!(matches!(SensitiveBytes::read_from(&mut reader, 6),
+                     Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+  }
+
+  // Miri isolates the test from the file system.
+  #[cfg(all(feature = "std", not(miri)))]
+  #[test]
+  fn read_from_file() {
+    let path = std::env::temp_dir().join(format!("sensitive-data-{}", std::process::id()));
+    std::fs::write(&path, [7u8; 64]).unwrap();
+    let a = SensitiveBytes::from_file_exact(&path, 32);
+    let b = SensitiveBytes::from_file_exact(&path, 65);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(&*a.unwrap().borrow(), &[7u8; 32]);
+    assert!(b.is_err());
+    assert!(SensitiveBytes::from_file_exact(&path, 32).is_err());
+  }
+
+  #[test]
+  fn converted() {
+    let a = SensitiveData::try_from([3u8; 32]).unwrap();
+    assert_eq!(*a.borrow(), [3; 32]);
+    let mut source = [4u8; 32];
+    let b = SensitiveData::try_from(&mut source).unwrap();
+    assert_eq!(source, [0; 32]);
+    assert_eq!(*b.borrow(), [4; 32]);
+    let c = SensitiveBytes::try_from(&b"secret"[..]).unwrap();
+    assert_eq!(&*c.borrow(), b"secret");
+  }
+
+  #[test]
+  fn decoded_from_hex() {
+    let key: Vec<u8> = (0..32).collect();
+    let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(&*SensitiveBytes::from_hex(&hex).unwrap().borrow(), &key[..]);
+    assert_eq!(&*SensitiveBytes::from_hex(&hex.to_uppercase()).unwrap()
+                                                              .borrow(),
+               &key[..]);
+    assert!(SensitiveBytes::from_hex("").unwrap().is_empty());
+  }
+
+  #[test]
+  fn malformed_hex() {
+    assert!(matches!(SensitiveBytes::from_hex("abc"), Err(Error::InvalidEncoding)));
+    // The decoded bytes are freed zeroized, as always.
+    assert!(matches!(SensitiveBytes::from_hex("abcdefgh"),
+                     Err(Error::InvalidEncoding)));
+    let last_freed = crate::tests::FREED.with(|freed| *freed.borrow().last().unwrap());
+    assert!(last_freed.1);
+  }
+
+  #[cfg(feature = "base64")]
+  #[test]
+  fn decoded_from_base64() {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let key: Vec<u8> = (0..32).collect();
+    for len in [0, 1, 2, 3, 32] {
+      let mut encoded = [0; 44];
+      let encoded_len = STANDARD.encode_slice(&key[..len], &mut encoded).unwrap();
+      let encoded = core::str::from_utf8(&encoded[..encoded_len]).unwrap();
+      assert_eq!(&*SensitiveBytes::from_base64(encoded).unwrap().borrow(),
+                 &key[..len]);
+    }
+  }
+
+  #[cfg(feature = "base64")]
+  #[test]
+  fn malformed_base64() {
+    for malformed in ["A", "AAA", "AA=A", "AA!=", "AAAA=", "AAF="] {
+      assert!(matches!(SensitiveBytes::from_base64(malformed),
+                       Err(Error::InvalidEncoding)),
+              "{} was decoded",
+              malformed);
+      let last_freed = crate::tests::FREED.with(|freed| *freed.borrow().last().unwrap());
+      assert!(last_freed.1);
+    }
+  }
+
+  #[test]
+  fn empty() {
+    let a = SensitiveBytes::from_slice(&[]).unwrap();
+    assert!(a.is_empty());
+    assert_eq!(a.memory_layout.size(), page_size());
+  }
+}
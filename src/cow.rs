@@ -0,0 +1,100 @@
+use core::ops::Deref;
+
+use super::SensitiveData;
+
+/// Either a borrowed or an owned `SensitiveData`, for APIs that only sometimes
+/// own the secret, so that one the caller already has doesn't have to be copied
+/// into new locked memory. It derefs to the `SensitiveData` either way.
+pub enum SensitiveCow<'a, T: ?Sized> {
+  Borrowed(&'a SensitiveData<T>),
+  Owned(SensitiveData<T>),
+}
+
+impl<T: ?Sized> SensitiveCow<'_, T> {
+  pub fn is_borrowed(&self) -> bool {
+    matches!(self, SensitiveCow::Borrowed(_))
+  }
+
+  pub fn is_owned(&self) -> bool {
+    !self.is_borrowed()
+  }
+}
+
+impl<T: Clone> SensitiveCow<'_, T> {
+  /// Clones the value straight into a new allocation, like
+  /// `SensitiveData::clone`, whether it's borrowed or owned.
+  pub fn to_owned(&self) -> SensitiveData<T> {
+    (**self).clone()
+  }
+
+  /// Returns the owned `SensitiveData`, or a clone of the borrowed one.
+  pub fn into_owned(self) -> SensitiveData<T> {
+    match self {
+      SensitiveCow::Borrowed(data) => data.clone(),
+      SensitiveCow::Owned(data) => data,
+    }
+  }
+}
+
+impl<T: ?Sized> Deref for SensitiveCow<'_, T> {
+  type Target = SensitiveData<T>;
+  fn deref(&self) -> &Self::Target {
+    match self {
+      SensitiveCow::Borrowed(data) => data,
+      SensitiveCow::Owned(data) => data,
+    }
+  }
+}
+
+impl<'a, T: ?Sized> From<&'a SensitiveData<T>> for SensitiveCow<'a, T> {
+  fn from(data: &'a SensitiveData<T>) -> Self {
+    SensitiveCow::Borrowed(data)
+  }
+}
+
+impl<T: ?Sized> From<SensitiveData<T>> for SensitiveCow<'_, T> {
+  fn from(data: SensitiveData<T>) -> Self {
+    SensitiveCow::Owned(data)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(target_family = "unix")]
+  use crate::tests::is_inaccessible;
+
+  fn first(key: impl Into<SensitiveCow<'static, [u8; 32]>>) -> u8 {
+    key.into().borrow()[0]
+  }
+
+  #[test]
+  fn borrowed_or_owned() {
+    let owned = SensitiveData::new([1u8; 32]).unwrap();
+    let borrowed = SensitiveCow::from(&owned);
+    assert!(borrowed.is_borrowed());
+    assert_eq!(borrowed.borrow()[0], 1);
+    let owned = SensitiveCow::from(SensitiveData::new([2u8; 32]).unwrap());
+    assert!(owned.is_owned());
+    assert_eq!(owned.borrow()[0], 2);
+    for cow in [&borrowed, &owned] {
+      assert!(cow.borrow().iter().all(|b| *b == cow.borrow()[31]));
+      #[cfg(target_family = "unix")]
+      assert!(is_inaccessible(cow));
+    }
+    assert_eq!(first(SensitiveData::new([3u8; 32]).unwrap()), 3);
+  }
+
+  #[test]
+  fn made_owned() {
+    let data = SensitiveData::new([1u8; 32]).unwrap();
+    let cow = SensitiveCow::from(&data);
+    let copy = cow.to_owned();
+    assert_ne!(copy.inner_ptr, data.inner_ptr);
+    assert_eq!(*copy.borrow(), [1; 32]);
+    let owned = cow.into_owned();
+    assert_ne!(owned.inner_ptr, data.inner_ptr);
+    let inner_ptr = owned.inner_ptr;
+    assert_eq!(SensitiveCow::from(owned).into_owned().inner_ptr, inner_ptr);
+  }
+}
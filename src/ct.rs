@@ -0,0 +1,255 @@
+//! Constant time helpers. Every function here inspects all of its input bytes,
+//! no matter where (or if) they differ.
+
+use core::{
+  cmp::Ordering,
+  hint::black_box,
+  ops::{BitAnd, BitOr, BitXor, Not},
+};
+
+/// The outcome of a constant time comparison, like `subtle::Choice`. It can be
+/// combined with `&`, `|`, `^` and `!` without branching, but it doesn't turn
+/// into a `bool` on its own. Branching on a secret is where its timing stops
+/// being constant, so that has to be asked for with `into_bool`.
+///
+/// ```
+/// # use sensitive_data::SensitiveData;
+/// let a = SensitiveData::new([1u8; 32]).unwrap();
+/// let b = SensitiveData::new([1u8; 32]).unwrap();
+/// if (a.ct_eq(&b) & b.ct_eq_slice(&[1; 32])).into_bool() {
+///   println!("Match");
+/// }
+/// ```
+///
+/// ```compile_fail
+/// # use sensitive_data::SensitiveData;
+/// let a = SensitiveData::new([1u8; 32]).unwrap();
+/// let b = SensitiveData::new([1u8; 32]).unwrap();
+/// if a.ct_eq(&b) {
+///   println!("Match");
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+#[must_use]
+pub struct CtBool(u8);
+
+impl CtBool {
+  /// Branches on the outcome, which leaks it through timing from here on.
+  pub fn into_bool(self) -> bool {
+    black_box(self.0) == 1
+  }
+
+  /// All ones if set, and all zeroes otherwise.
+  fn mask(self) -> u8 {
+    black_box(self.0).wrapping_neg()
+  }
+}
+
+impl From<bool> for CtBool {
+  fn from(value: bool) -> Self {
+    CtBool(black_box(u8::from(value)))
+  }
+}
+
+impl BitAnd for CtBool {
+  type Output = CtBool;
+  fn bitand(self, rhs: CtBool) -> CtBool {
+    CtBool(black_box(self.0 & rhs.0))
+  }
+}
+
+impl BitOr for CtBool {
+  type Output = CtBool;
+  fn bitor(self, rhs: CtBool) -> CtBool {
+    CtBool(black_box(self.0 | rhs.0))
+  }
+}
+
+impl BitXor for CtBool {
+  type Output = CtBool;
+  fn bitxor(self, rhs: CtBool) -> CtBool {
+    CtBool(black_box(self.0 ^ rhs.0))
+  }
+}
+
+impl Not for CtBool {
+  type Output = CtBool;
+  fn not(self) -> CtBool {
+    CtBool(black_box(self.0 ^ 1))
+  }
+}
+
+/// Set if `difference` is 0. Only 0 wraps around to set the top bit.
+fn is_zero(difference: u8) -> CtBool {
+  CtBool((u16::from(black_box(difference)).wrapping_sub(1) >> 15) as u8)
+}
+
+/// Compares `a` and `b` without short-circuiting on the first difference. Only
+/// the lengths, which aren't considered secret, may leak through timing.
+pub(crate) fn eq(a: &[u8], b: &[u8]) -> CtBool {
+  if a.len() != b.len() {
+    return CtBool(0);
+  }
+  let difference = a.iter()
+                    .zip(b)
+                    .fold(0u8, |acc, (a, b)| black_box(acc | (a ^ b)));
+  is_zero(difference)
+}
+
+/// Like `eq`, but a length mismatch doesn't return early. Every byte of both
+/// is inspected, with the shorter one padded with zeroes.
+pub(crate) fn eq_padded(a: &[u8], b: &[u8]) -> CtBool {
+  let mut difference = black_box((a.len() != b.len()) as u8);
+  for i in 0..a.len().max(b.len()) {
+    let a = a.get(i).copied().unwrap_or(0);
+    let b = b.get(i).copied().unwrap_or(0);
+    difference = black_box(difference | (a ^ b));
+  }
+  is_zero(difference)
+}
+
+/// Compares `a` and `b` lexicographically, like `Ord` for slices, without
+/// short-circuiting on the first difference. Only the lengths may leak through
+/// timing.
+pub(crate) fn cmp(a: &[u8], b: &[u8]) -> Ordering {
+  // Each is 1 once the first difference is found to go that way.
+  let (mut less, mut greater) = (0u8, 0u8);
+  for (a, b) in a.iter().zip(b) {
+    let (a, b) = (u16::from(*a), u16::from(*b));
+    // The subtraction only wraps around, setting the top bit, if it's smaller.
+    let lt = (a.wrapping_sub(b) >> 15) as u8;
+    let gt = (b.wrapping_sub(a) >> 15) as u8;
+    let undecided = !(less | greater) & 1;
+    less = black_box(less | (lt & undecided));
+    greater = black_box(greater | (gt & undecided));
+  }
+  match (black_box(less), black_box(greater)) {
+    (1, _) => Ordering::Less,
+    (_, 1) => Ordering::Greater,
+    _ => a.len().cmp(&b.len()),
+  }
+}
+
+/// Writes `a` to `out` if `condition` is set, and `b` otherwise, through a mask
+/// instead of a branch. Every byte of both is read either way.
+pub(crate) fn select(condition: CtBool, a: &[u8], b: &[u8], out: &mut [u8]) {
+  debug_assert!(a.len() == out.len() && b.len() == out.len());
+  let mask = condition.mask();
+  for ((out, a), b) in out.iter_mut().zip(a).zip(b) {
+    *out = black_box((a & mask) | (b & !mask));
+  }
+}
+
+/// Decodes a hex digit without branching on it. Anything that isn't one comes
+/// out as 0x100 or more.
+fn hex_digit(c: u8) -> u16 {
+  let c = u16::from(c);
+  let digit = c.wrapping_sub(u16::from(b'0'));
+  let letter = (c | 0x20).wrapping_sub(u16::from(b'a'));
+  let digit_mask = u16::from(digit < 10).wrapping_neg();
+  let letter_mask = u16::from(letter < 6).wrapping_neg();
+  (digit & digit_mask)
+  | (letter.wrapping_add(10) & letter_mask)
+  | (0x100 & !(digit_mask | letter_mask))
+}
+
+/// Decodes `hex` into `out`, which must be half as long, without branching on
+/// the digits. Returns whether every character was a hex digit, in which case
+/// `out` holds something meaningful.
+pub(crate) fn decode_hex(hex: &[u8], out: &mut [u8]) -> bool {
+  debug_assert_eq!(hex.len(), 2 * out.len());
+  let mut invalid = 0u16;
+  for (byte, pair) in out.iter_mut().zip(hex.chunks_exact(2)) {
+    let (high, low) = (hex_digit(pair[0]), hex_digit(pair[1]));
+    invalid = black_box(invalid | ((high | low) >> 8));
+    *byte = (high << 4 | low) as u8;
+  }
+  black_box(invalid) == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn equal() {
+    assert!(eq(&[1, 2, 3], &[1, 2, 3]).into_bool());
+    assert!(eq(&[], &[]).into_bool());
+  }
+
+  #[test]
+  fn every_position_compared() {
+    let a = [0u8; 64];
+    for i in 0..a.len() {
+      let mut b = a;
+      b[i] = 1 << (i % 8);
+      assert!(!eq(&a, &b).into_bool(), "Difference at {} not found", i);
+    }
+  }
+
+  #[test]
+  fn different_lengths() {
+    assert!(!eq(&[1, 2, 3], &[1, 2]).into_bool());
+  }
+
+  #[test]
+  fn padded() {
+    assert!(eq_padded(&[1, 2, 3], &[1, 2, 3]).into_bool());
+    assert!(eq_padded(&[], &[]).into_bool());
+    assert!(!eq_padded(&[1, 2, 3], &[1, 2, 4]).into_bool());
+    assert!(!eq_padded(&[1, 2, 0], &[1, 2]).into_bool());
+    assert!(!eq_padded(&[1, 2], &[1, 2, 0]).into_bool());
+  }
+
+  #[test]
+  fn combined() {
+    for a in [false, true] {
+      for b in [false, true] {
+        let (ct_a, ct_b) = (CtBool::from(a), CtBool::from(b));
+        assert_eq!((ct_a & ct_b).into_bool(), a & b);
+        assert_eq!((ct_a | ct_b).into_bool(), a | b);
+        assert_eq!((ct_a ^ ct_b).into_bool(), a ^ b);
+        assert_eq!((!ct_a).into_bool(), !a);
+        // The combinations stay 0 or 1, which `mask` depends on.
+        assert!((ct_a & ct_b).0 <= 1 && (ct_a | ct_b).0 <= 1 && (!ct_a).0 <= 1);
+      }
+    }
+    for difference in 0..=255 {
+      assert_eq!(is_zero(difference).0, u8::from(difference == 0));
+    }
+  }
+
+  #[test]
+  fn compared() {
+    for (a, b) in [(&[1, 2, 3][..], &[1, 2, 3][..]),
+                   (&[1, 2, 3], &[1, 2, 4]),
+                   (&[1, 3, 0], &[1, 2, 9]),
+                   (&[0xff, 0], &[0, 0xff]),
+                   (&[1, 2], &[1, 2, 3]),
+                   (&[2], &[1, 2, 3]),
+                   (&[], &[])]
+    {
+      assert_eq!(cmp(a, b), a.cmp(b), "{:?} <=> {:?}", a, b);
+      assert_eq!(cmp(b, a), b.cmp(a), "{:?} <=> {:?}", b, a);
+    }
+  }
+
+  #[test]
+  fn selected() {
+    let mut out = [0; 3];
+    select(true.into(), &[1, 2, 3], &[4, 5, 6], &mut out);
+    assert_eq!(out, [1, 2, 3]);
+    select(false.into(), &[1, 2, 3], &[4, 5, 6], &mut out);
+    assert_eq!(out, [4, 5, 6]);
+  }
+
+  #[test]
+  fn hex() {
+    let mut out = [0; 4];
+    assert!(decode_hex(b"09afAF7e", &mut out));
+    assert_eq!(out, [0x09, 0xaf, 0xaf, 0x7e]);
+    for invalid in [&b"0g"[..], b"g0", b"/0", b":0", b"@0", b"`0", b"G0", b"0 "] {
+      assert!(!decode_hex(invalid, &mut [0]), "{:?} was decoded", invalid);
+    }
+  }
+}
@@ -1,23 +1,89 @@
-pub use std::{alloc::LayoutError, io::Error as IoError};
-use std::{error, fmt};
+pub use core::alloc::LayoutError;
+use core::{alloc::Layout, fmt};
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
+pub use std::io::Error as IoError;
 
 #[derive(Debug)]
 pub enum Error {
   LayoutError(LayoutError),
   IoError(IoError),
+  /// The memory couldn't be locked because of the limit on how much memory may
+  /// be locked, such as `RLIMIT_MEMLOCK` on unix. Holds the error from the
+  /// protector, which has the raw OS error.
+  LockLimitExceeded(IoError),
+  /// The allocator couldn't provide memory for this layout.
+  AllocError(Layout),
+  /// The value was frozen, and can't be borrowed mutably.
+  Frozen,
+  /// Every slot of the `SensitiveArena` is in use.
+  ArenaFull,
+  /// The input to a decoder, such as `SensitiveBytes::from_hex`, was malformed.
+  /// Where is left out, since it would tell something about the secret.
+  InvalidEncoding,
+  /// The value doesn't match its integrity tag, so it was changed behind the
+  /// back of this crate.
+  Corrupted,
+  /// The memory couldn't be protected again after an earlier borrow, so the
+  /// value may be exposed. See `SensitiveData::is_poisoned`.
+  Poisoned,
+  /// The range spans more pages than the window of a `SensitivePagedBytes`
+  /// holds, or the window has no pages at all.
+  WindowTooSmall,
+  /// The destination of a copy, such as with `SensitiveData::copy_to`, isn't
+  /// as long as the source.
+  LengthMismatch,
+  /// The value was already dropped and zeroized by `SensitiveData::zeroize`.
+  Consumed,
+  /// The length asked for, such as of a `SensitiveSlice`, takes more than
+  /// `isize::MAX` bytes once it's rounded up to whole pages, along with any
+  /// guard pages, so it could never be allocated.
+  TooLarge,
 }
 
 impl fmt::Display for Error {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "SensitiveData error")
+    match self {
+      Error::LayoutError(e) => write!(f, "invalid layout for the value: {}", e),
+      Error::IoError(e) => write!(f, "could not protect the memory: {}", e),
+      Error::LockLimitExceeded(e) => write!(f, "the limit on locked memory was reached: {}", e),
+      Error::AllocError(layout) => write!(f,
+                                          "could not allocate {} bytes aligned to {}",
+                                          layout.size(),
+                                          layout.align()),
+      Error::Frozen => write!(f, "the value is frozen, and can't be borrowed mutably"),
+      Error::ArenaFull => write!(f, "every slot of the arena is in use"),
+      Error::InvalidEncoding => write!(f, "the encoded input is malformed"),
+      Error::Corrupted => write!(f, "the value doesn't match its integrity tag"),
+      Error::Poisoned => write!(f, "the memory could not be protected again after a borrow"),
+      Error::WindowTooSmall => write!(f, "the range doesn't fit in the window of pages"),
+      Error::LengthMismatch => write!(f, "the destination isn't as long as the source"),
+      Error::Consumed => write!(f, "the value was already zeroized"),
+      Error::TooLarge => {
+        write!(f,
+               "the requested length exceeds isize::MAX bytes once page-rounded")
+      }
+    }
   }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
   fn source(&self) -> Option<&(dyn error::Error + 'static)> {
     match self {
       Error::LayoutError(ref l) => Some(l),
-      Error::IoError(ref e) => Some(e),
+      Error::IoError(ref e) | Error::LockLimitExceeded(ref e) => Some(e),
+      Error::AllocError(_)
+      | Error::Frozen
+      | Error::ArenaFull
+      | Error::InvalidEncoding
+      | Error::Corrupted
+      | Error::Poisoned
+      | Error::WindowTooSmall
+      | Error::LengthMismatch
+      | Error::Consumed
+      | Error::TooLarge => None,
     }
   }
 }
@@ -33,3 +99,84 @@ impl From<LayoutError> for Error {
     Error::LayoutError(other)
   }
 }
+
+/// Stands in for `std::io::Error` without the `std` feature, holding the raw
+/// OS error code when there is one.
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoError {
+  code: Option<i32>,
+}
+
+#[cfg(not(feature = "std"))]
+impl IoError {
+  pub fn from_raw_os_error(code: i32) -> IoError {
+    IoError { code: Some(code) }
+  }
+
+  pub fn raw_os_error(&self) -> Option<i32> {
+    self.code
+  }
+
+  #[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "dragonfly"))]
+  pub fn last_os_error() -> IoError {
+    Self::from_raw_os_error(unsafe { *libc::__errno_location() })
+  }
+
+  #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+  pub fn last_os_error() -> IoError {
+    Self::from_raw_os_error(unsafe { *libc::__errno() })
+  }
+
+  #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+  pub fn last_os_error() -> IoError {
+    Self::from_raw_os_error(unsafe { *libc::__error() })
+  }
+
+  #[cfg(any(target_os = "solaris", target_os = "illumos"))]
+  pub fn last_os_error() -> IoError {
+    Self::from_raw_os_error(unsafe { *libc::___errno() })
+  }
+
+  #[cfg(target_family = "windows")]
+  pub fn last_os_error() -> IoError {
+    Self::from_raw_os_error(unsafe { winapi::um::errhandlingapi::GetLastError() } as i32)
+  }
+
+  #[cfg(not(any(target_os = "linux",
+                target_os = "emscripten",
+                target_os = "android",
+                target_os = "netbsd",
+                target_os = "openbsd",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "dragonfly",
+                target_os = "solaris",
+                target_os = "illumos",
+                target_family = "windows")))]
+  pub fn last_os_error() -> IoError {
+    IoError { code: None }
+  }
+}
+
+#[cfg(not(feature = "std"))]
+impl fmt::Display for IoError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.code {
+      Some(code) => write!(f, "OS error {}", code),
+      None => write!(f, "Unsupported operation"),
+    }
+  }
+}
+
+/// The error returned by operations the platform doesn't support.
+#[cfg(feature = "std")]
+pub(crate) fn unsupported() -> IoError {
+  IoError::from(std::io::ErrorKind::Unsupported)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn unsupported() -> IoError {
+  IoError { code: None }
+}
@@ -0,0 +1,48 @@
+use super::{Error, SensitiveData};
+
+/// Types that keep all of their bytes inline, with nothing on the heap or
+/// anywhere else behind a pointer, so that locking a `SensitiveData` of them
+/// protects the whole secret.
+///
+/// That isn't the case for `Box`, `Vec`, `String` or references. A
+/// `SensitiveData<Vec<u8>>` only locks the pointer, length and capacity, while
+/// the bytes themselves stay on the regular heap, where they can be swapped
+/// out or dumped, and aren't zeroized. Use `SensitiveBytes`,
+/// `SensitiveString` or `SensitiveVec` for those instead.
+///
+/// # Safety
+/// The type must not contain any pointer, reference, or handle to memory
+/// outside of itself.
+pub unsafe trait InlineSecret {}
+
+macro_rules! inline_secret {
+  ($($t:ty),*) => {
+    $(unsafe impl InlineSecret for $t {})*
+  };
+}
+
+inline_secret!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, bool, char, f32,
+               f64);
+
+unsafe impl<T: InlineSecret, const N: usize> InlineSecret for [T; N] {}
+unsafe impl<A: InlineSecret, B: InlineSecret> InlineSecret for (A, B) {}
+unsafe impl<A: InlineSecret, B: InlineSecret, C: InlineSecret> InlineSecret for (A, B, C) {}
+
+impl<T: InlineSecret> SensitiveData<T> {
+  /// Like `new`, but only accepts values that are stored inline, so that the
+  /// whole secret is protected. See `InlineSecret`.
+  ///
+  /// ```
+  /// # use sensitive_data::SensitiveData;
+  /// let key = SensitiveData::new_inline([0u8; 32]).unwrap();
+  /// ```
+  ///
+  /// ```compile_fail
+  /// # use sensitive_data::SensitiveData;
+  /// let key = SensitiveData::new_inline(Box::new(0u8)).unwrap();
+  /// ```
+  #[inline(always)]
+  pub fn new_inline(t: T) -> Result<Self, Error> {
+    Self::new(t)
+  }
+}
@@ -1,355 +1,3267 @@
-use std::{
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
+
+extern crate alloc;
+
+#[cfg(feature = "allocator-api")]
+use alloc::alloc::Global;
+use alloc::boxed::Box;
+#[cfg(feature = "allocator-api")]
+use core::alloc::Allocator;
+use core::{
   alloc::{Layout, LayoutError},
-  marker::PhantomPinned,
-  ops::{Deref, DerefMut},
-  sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+  fmt,
+  hash::Hasher,
+  hint::black_box,
+  marker::{PhantomData, PhantomPinned},
+  mem::{ManuallyDrop, MaybeUninit},
+  ops::{Deref, DerefMut, Index, IndexMut},
+  pin::Pin,
+  sync::atomic::{compiler_fence, fence, Ordering},
+};
+#[cfg(not(loom))]
+use core::{
+  hint::spin_loop,
+  sync::atomic::{AtomicBool, AtomicU8, AtomicUsize},
 };
 
-#[cfg(target_family = "unix")]
-use libc::c_void;
-#[cfg(target_family = "windows")]
-use winapi::{
-  ctypes::c_void,
-  um::{memoryapi, sysinfoapi, winnt},
+// The borrow state is modeled by loom in `loom_tests`, by building with
+// `--cfg loom`.
+#[cfg(loom)]
+use loom::{
+  hint::spin_loop,
+  sync::atomic::{AtomicBool, AtomicU8, AtomicUsize},
 };
 
+mod arena;
+mod builder;
+mod bytes;
+mod cow;
+mod ct;
 mod err;
-pub use err::Error;
+mod inline;
+#[cfg(feature = "paged")]
+mod paged;
+mod process;
+mod protector;
+#[cfg(feature = "secrecy")]
+mod secrecy_compat;
+mod secret;
+#[cfg(feature = "serde")]
+mod serialization;
+mod slice;
+mod string;
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+mod sys;
+mod vec;
+#[cfg(feature = "getrandom")]
+mod wipe;
+pub use arena::{SensitiveArena, SensitiveHandle};
+pub use builder::SensitiveDataBuilder;
+pub use bytes::SensitiveBytes;
+pub use cow::SensitiveCow;
+pub use ct::CtBool;
+pub use err::{Error, IoError};
+pub use inline::InlineSecret;
+#[cfg(feature = "paged")]
+pub use paged::SensitivePagedBytes;
+pub use protector::MemoryProtector;
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+pub use protector::OsProtector;
+pub use secret::{Opened, Sealed, Secret, State};
+pub use slice::SensitiveSlice;
+pub use string::SensitiveString;
+pub use vec::SensitiveVec;
+#[cfg(feature = "getrandom")]
+pub use wipe::WipePolicy;
 
-struct HolderInner<T> {
-  value: T,
+// `repr(C)` keeps `value` at the start of the allocation, which is also what
+// allows it to be an unsized slice.
+#[repr(C)]
+struct HolderInner<T: ?Sized> {
   _marker: PhantomPinned,
+  value: T,
+}
+
+/// The state that changes on shared borrows. It's kept outside of the
+/// protected memory, which is read only while the value is borrowed, so that a
+/// `SensitiveData` can hold another one.
+struct BorrowState {
+  deref_counter: AtomicUsize,
+  /// Set while a `DerefMutHolder` exists.
+  mut_borrowed: AtomicBool,
+  /// The current `Protection` of the memory.
+  protection: AtomicU8,
+  /// Set when the memory couldn't be protected again after a borrow.
+  poisoned: AtomicBool,
 }
 
-pub struct SensitiveData<T> {
+pub struct SensitiveData<T: ?Sized> {
   memory_layout: Layout,
   inner_ptr: *mut HolderInner<T>,
-  deref_counter: AtomicUsize,
+  /// The size of the value, so that `Drop` can report it without a reference
+  /// into memory that may be inaccessible, or no longer hold a value.
+  value_size: usize,
+  state: Box<BorrowState>,
+  /// The protection of the memory while it isn't borrowed.
+  idle: Protection,
+  locked: bool,
+  /// Set by `freeze`, after which the value is never written again.
+  frozen: bool,
+  /// Set by `zeroize`, after which there's no value left to borrow or drop.
+  consumed: bool,
+  /// Whether forked children get zeroes instead of the value.
+  wipe_on_fork: bool,
+  /// Whether the memory is surrounded by inaccessible pages.
+  guard_pages: bool,
+  /// Whether the memory was excluded from core dumps.
+  dump_excluded: bool,
+  protector: &'static dyn MemoryProtector,
+  /// Where the memory comes from.
+  #[cfg(feature = "allocator-api")]
+  allocator: &'static (dyn Allocator + Sync),
+  /// Computes the checksum of the value, which is kept in the last bytes of
+  /// the memory, and checked on every borrow.
+  integrity: Option<fn(&T) -> u64>,
+  /// Runs on the value right before it's dropped.
+  #[cfg(feature = "zeroize")]
+  before_drop: Option<fn(&mut T)>,
+  /// What's written over the memory before the final zeroes.
+  #[cfg(feature = "getrandom")]
+  wipe: WipePolicy,
+  /// Runs with the size of the value once it's dropped and zeroized.
+  on_drop: Option<Box<dyn FnOnce(usize) + Send>>,
+}
+
+// `inner_ptr` only ever points to the heap allocation owned by this
+// `SensitiveData`, so the raw pointer doesn't tie it to any thread. Sending the
+// container moves the `T` with it, and sharing it hands out `&T` through
+// `borrow()`, so the bounds are the same as for `Box<T>`. The protector is
+// `Sync` by definition. The deref holders are made `!Send` on purpose, but
+// sharing them only hands out `&T`, and making a `DerefHolder` readable is
+// synchronized through its atomic state. The `on_drop` hook is only ever
+// touched through `&mut self`, so it only has to be `Send`.
+unsafe impl<T: ?Sized + Send> Send for SensitiveData<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for SensitiveData<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for DerefHolder<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for DerefMutHolder<'_, T> {}
+
+/// Options applied when allocating a `SensitiveData`.
+#[derive(Clone, Copy)]
+struct Options {
+  locking: Locking,
+  /// Locks the pages only as they're first touched, and has the allocator
+  /// zero them, so that it can leave fresh pages untouched.
+  lock_on_fault: bool,
+  /// Discards the pages before they're locked on fault, instead of having the
+  /// allocator zero them, so that they aren't touched until they're written.
+  lazy: bool,
+  idle: Protection,
+  wipe_on_fork: bool,
+  guard_pages: bool,
+  exclude_from_dump: bool,
+  /// Makes room for the integrity tag.
+  integrity: bool,
+  /// The alignment of the memory, if more than a page.
+  align: usize,
+  protector: &'static dyn MemoryProtector,
+  #[cfg(feature = "allocator-api")]
+  allocator: &'static (dyn Allocator + Sync),
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    // Nothing can be locked or protected on WASM, so the memory isn't
+    // reported as either.
+    let wasm = cfg!(all(not(miri), target_arch = "wasm32"));
+    Options { locking: if wasm {
+                Locking::Disabled
+              } else {
+                Locking::default()
+              },
+              lock_on_fault: false,
+              lazy: false,
+              idle: if wasm {
+                Protection::Readable
+              } else {
+                Protection::Inaccessible
+              },
+              wipe_on_fork: false,
+              guard_pages: cfg!(feature = "guard-pages"),
+              exclude_from_dump: true,
+              integrity: false,
+              align: 1,
+              protector: protector::default_protector(),
+              #[cfg(feature = "allocator-api")]
+              allocator: &Global }
+  }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Locking {
+  /// Fail if the memory can't be locked.
+  #[default]
+  Required,
+  /// Lock the memory if the limits allow it.
+  BestEffort,
+  Disabled,
+}
+
+/// Set in `deref_counter` while the first reader makes the memory readable, or
+/// while the last reader makes it inaccessible again. Other readers spin until
+/// the transition is complete, so nobody dereferences a page in the wrong state.
+///
+/// The orderings of `deref_counter` carry the protection changes between
+/// readers:
+///
+/// - Claiming `TRANSITIONING` is `Acquire`, so that the change starts after
+///   whatever the previous transition did, including its update of
+///   `protection`.
+/// - Leaving `TRANSITIONING` is `Release`, and every reader loads the counter
+///   with `Acquire` before registering, so a reader that finds the memory
+///   readable happens after the `make_readable` that made it so.
+/// - Registering and unregistering on top of other readers is `AcqRel`, so
+///   that the last reader to leave happens after every read, and the memory
+///   isn't made inaccessible under anyone.
+///
+/// This is what allows `protection` itself to be `Relaxed`. The model in
+/// `loom_tests` checks it.
+const TRANSITIONING: usize = !(usize::MAX >> 1);
+
+/// The protection of the memory, as set through its protector.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Protection {
+  /// Neither readable nor writable, which is how the memory is kept at rest.
+  Inaccessible,
+  Readable,
+  Writable,
+}
+
+/// States of `DerefHolder::permissions`.
+const UNTOUCHED: u8 = 0;
+const CHANGING: u8 = 1;
+const CHANGED: u8 = 2;
+
+/// A borrow of the value, which keeps the memory readable while it lives.
+///
+/// Every holder counts as one reader in `deref_counter` from its first
+/// dereference until it's dropped, no matter how often it's dereferenced in
+/// between. A holder from `borrow` that's never dereferenced never counts, so
+/// the memory stays inaccessible. The memory is readable while at least one
+/// holder counts, and made inaccessible again when the last one is dropped.
+///
+/// Holders are deliberately not `Send`, so that they can't be held across an
+/// `.await` in a future that has to be. That would leave the memory readable
+/// for as long as the future is suspended. In async code, use `with_ref` or
+/// `with_mut` to keep the access within a synchronous closure instead:
+///
+/// ```
+/// # use sensitive_data::SensitiveData;
+/// # fn require_send(_: impl Send) {}
+/// # async fn elsewhere() {}
+/// let secret = SensitiveData::new([1u8; 32]).unwrap();
+/// require_send(async move {
+///   let first = secret.with_ref(|key| key[0]);
+///   elsewhere().await;
+///   first
+/// });
+/// ```
+///
+/// ```compile_fail
+/// # use sensitive_data::SensitiveData;
+/// # fn require_send(_: impl Send) {}
+/// # async fn elsewhere() {}
+/// let secret = SensitiveData::new([1u8; 32]).unwrap();
+/// require_send(async move {
+///   let key = secret.borrow();
+///   elsewhere().await;
+///   key[0]
+/// });
+/// ```
+pub struct DerefHolder<'holder, T: ?Sized> {
+  holder: &'holder SensitiveData<T>,
+  permissions: AtomicU8,
+  _not_send: PhantomData<*const ()>,
 }
 
-pub struct DerefHolder<'holder, T> {
+/// A mutable borrow of the value, which keeps the memory writable while it
+/// lives. Like `DerefHolder`, it's deliberately not `Send`.
+pub struct DerefMutHolder<'holder, T: ?Sized> {
+  /// Shared, so that `borrow_mut_shared` can hand one out, but exclusive in
+  /// every other way.
   holder: &'holder SensitiveData<T>,
-  changed_permissions: AtomicBool,
+  _exclusive: PhantomData<&'holder mut T>,
+  _not_send: PhantomData<*const ()>,
+}
+
+/// A borrow of the value as a raw pointer, for foreign code that takes a
+/// pointer and a length. The memory is readable for as long as this lives, and
+/// the pointer must not be used after it's dropped.
+pub struct RawBorrow<'holder, T: ?Sized> {
+  borrow: DerefHolder<'holder, T>,
+}
+
+impl<T: ?Sized> RawBorrow<'_, T> {
+  #[inline(always)]
+  pub fn as_ptr(&self) -> *const u8 {
+    &*self.borrow as *const T as *const u8
+  }
+
+  /// The size of the value in bytes.
+  #[inline(always)]
+  pub fn len(&self) -> usize {
+    core::mem::size_of_val(&*self.borrow)
+  }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
 }
 
-pub struct DerefMutHolder<'holder, T> {
-  holder: &'holder mut SensitiveData<T>,
+/// A borrow of part of the value, such as one of its fields, from `project`.
+/// Only the part can be reached through it, though the whole value is
+/// readable for as long as it lives, since they share pages.
+pub struct ProjectedHolder<'holder, T: ?Sized, U: ?Sized> {
+  borrow: DerefHolder<'holder, T>,
+  /// Points into the value, which `borrow` keeps readable.
+  part: *const U,
+}
+
+impl<T: ?Sized, U: ?Sized> Deref for ProjectedHolder<'_, T, U> {
+  type Target = U;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.part }
+  }
 }
 
-impl<T> Drop for DerefMutHolder<'_, T> {
+impl<T: ?Sized> Drop for DerefMutHolder<'_, T> {
   fn drop(&mut self) {
+    self.holder.write_tag();
+    if self.holder.make_idle().is_err() {
+      self.holder.poison();
+    }
     self.holder
-        .make_inaccessible()
-        .expect("Could not make SensitiveData inaccessible");
+        .state
+        .mut_borrowed
+        .store(false, Ordering::Release);
   }
 }
 
-impl<T> Drop for DerefHolder<'_, T> {
+impl<T: ?Sized> Drop for DerefHolder<'_, T> {
   fn drop(&mut self) {
-    if self.changed_permissions.load(Ordering::Acquire)
-       && self.holder.deref_counter.fetch_sub(1, Ordering::AcqRel) == 1
-    {
-      self.holder
-          .make_inaccessible()
-          .expect("Could not make SensitiveData readable");
+    if self.permissions.load(Ordering::Acquire) == CHANGED && self.holder.release_read().is_err() {
+      self.holder.poison();
     }
   }
 }
 
-impl<T> Drop for SensitiveData<T> {
+impl<T: ?Sized> Drop for SensitiveData<T> {
   fn drop(&mut self) {
-    self.make_writable()
-        .expect("Could not make SensitiveData writable");
-    unsafe {
-      std::ptr::drop_in_place(self.inner_ptr);
+    let writable = self.make_writable();
+    if writable.is_ok() && !self.consumed {
+      #[cfg(feature = "zeroize")]
+      if let Some(before_drop) = self.before_drop {
+        before_drop(unsafe { &mut (*self.inner_ptr).value });
+      }
+      unsafe {
+        core::ptr::drop_in_place(self.inner_ptr);
+      }
     }
-    self.zeroize_inner();
-    unsafe {
-      std::alloc::dealloc(self.inner_ptr as *mut u8, self.memory_layout);
+    // If the protection couldn't be changed, the value is leaked rather than
+    // dropped, and `free` leaks the memory too.
+    let freed = self.free();
+    if let Some(on_drop) = self.on_drop.take() {
+      on_drop(self.value_size);
+    }
+    if let Err(e) = writable {
+      drop_failed("Could not make SensitiveData writable", e);
+    }
+    if let Err((message, e)) = freed {
+      drop_failed(message, e);
+    }
+  }
+}
+
+/// Called with what failed in `Drop`, set by `on_drop_failure`.
+#[cfg(feature = "abort-free-drop")]
+static DROP_FAILURE_HOOK: core::sync::atomic::AtomicPtr<()> =
+  core::sync::atomic::AtomicPtr::new(core::ptr::null_mut());
+
+#[cfg(feature = "abort-free-drop")]
+impl SensitiveData<()> {
+  /// Sets the function that's told when dropping a `SensitiveData` or a
+  /// `SensitiveHandle` fails to change the protection of the memory, or to
+  /// unlock it. The drop carries on as well as it can, but it never panics.
+  /// The memory is only cleared and freed if it, and its guard pages, could be
+  /// made writable, and is leaked otherwise. Without a hook the failure goes
+  /// unnoticed.
+  ///
+  /// This applies to the whole process. The hook runs inside `Drop`, maybe
+  /// while unwinding, so it must not panic itself.
+  pub fn on_drop_failure(hook: fn(&str, &Error)) {
+    DROP_FAILURE_HOOK.store(hook as *mut (), Ordering::Release);
+  }
+}
+
+/// Reports that `Drop` couldn't do what `message` says. It panics unless the
+/// `abort-free-drop` feature is enabled, in which case the hook from
+/// `on_drop_failure` gets it instead, or it's already unwinding with `std`,
+/// where panicking again would abort.
+#[cfg_attr(not(feature = "abort-free-drop"), track_caller)]
+fn drop_failed(message: &str, e: err::IoError) {
+  #[cfg(feature = "abort-free-drop")]
+  {
+    let hook = DROP_FAILURE_HOOK.load(Ordering::Acquire);
+    if !hook.is_null() {
+      let hook = unsafe { core::mem::transmute::<*mut (), fn(&str, &Error)>(hook) };
+      hook(message, &Error::IoError(e));
+    }
+  }
+  #[cfg(not(feature = "abort-free-drop"))]
+  {
+    #[cfg(feature = "std")]
+    if std::thread::panicking() {
+      return;
     }
+    panic!("{}: {:?}", message, e);
   }
 }
 
-impl<'deref_holder, T> Deref for DerefHolder<'_, T> {
+impl<T: ?Sized> Deref for DerefHolder<'_, T> {
   type Target = T;
   fn deref(&self) -> &Self::Target {
-    if !self.changed_permissions.swap(true, Ordering::AcqRel)
-       && self.holder.deref_counter.fetch_add(1, Ordering::AcqRel) == 0
-    {
-      self.holder
-          .make_readable()
-          .expect("Could not make SensitiveData readable");
+    loop {
+      match self.permissions
+                .compare_exchange(UNTOUCHED, CHANGING, Ordering::AcqRel, Ordering::Acquire)
+      {
+        Ok(_) => {
+          if self.holder.is_poisoned() {
+            self.permissions.store(UNTOUCHED, Ordering::Release);
+            panic!("SensitiveData is poisoned");
+          }
+          if self.holder.consumed {
+            self.permissions.store(UNTOUCHED, Ordering::Release);
+            panic!("SensitiveData was zeroized");
+          }
+          if let Err(e) = self.holder.acquire_read() {
+            self.permissions.store(UNTOUCHED, Ordering::Release);
+            panic!("Could not make SensitiveData readable: {:?}", e);
+          }
+          if !self.holder.tag_matches() {
+            self.permissions.store(UNTOUCHED, Ordering::Release);
+            let _ = self.holder.release_read();
+            panic!("SensitiveData was corrupted");
+          }
+          self.permissions.store(CHANGED, Ordering::Release);
+          break;
+        }
+        Err(CHANGING) => spin_loop(),
+        Err(_) => break,
+      }
     }
     unsafe { &(*self.holder.inner_ptr).value }
   }
 }
 
-impl<T> Deref for DerefMutHolder<'_, T> {
+impl<T: ?Sized> Deref for DerefMutHolder<'_, T> {
   type Target = T;
   fn deref(&self) -> &Self::Target {
-    self.holder
-        .make_readable()
-        .expect("Could not make SensitiveData readable");
     unsafe { &(*self.holder.inner_ptr).value }
   }
 }
 
-impl<T> DerefMut for DerefMutHolder<'_, T> {
+impl<T: ?Sized> DerefMut for DerefMutHolder<'_, T> {
   fn deref_mut(&mut self) -> &mut Self::Target {
-    self.holder
-        .make_writable()
-        .expect("Could not make SensitiveData writable");
     unsafe { &mut (*self.holder.inner_ptr).value }
   }
 }
 
-#[cfg(target_family = "unix")]
-#[inline(always)]
-fn page_size() -> usize {
-  (unsafe { libc::sysconf(libc::_SC_PAGESIZE) }) as usize
+// Indexing goes through `Deref` anyway, but these let the holders be passed
+// where `Index` is required.
+impl<T: ?Sized + Index<I>, I> Index<I> for DerefHolder<'_, T> {
+  type Output = T::Output;
+  #[inline(always)]
+  fn index(&self, index: I) -> &Self::Output {
+    &(**self)[index]
+  }
 }
 
-#[cfg(target_family = "windows")]
-#[inline(always)]
-fn page_size() -> usize {
-  let mut system_info = sysinfoapi::SYSTEM_INFO::default();
-  unsafe { sysinfoapi::GetSystemInfo(&mut system_info as *mut _) };
-  system_info.dwPageSize as usize
+impl<T: ?Sized + Index<I>, I> Index<I> for DerefMutHolder<'_, T> {
+  type Output = T::Output;
+  #[inline(always)]
+  fn index(&self, index: I) -> &Self::Output {
+    &(**self)[index]
+  }
 }
 
-impl<T: Sized> SensitiveData<T> {
-  fn layout() -> Result<Layout, LayoutError> {
-    Ok(Layout::new::<T>().align_to(page_size())?.pad_to_align())
+impl<T: ?Sized + IndexMut<I>, I> IndexMut<I> for DerefMutHolder<'_, T> {
+  #[inline(always)]
+  fn index_mut(&mut self, index: I) -> &mut Self::Output {
+    &mut (**self)[index]
   }
+}
 
-  #[cfg(target_family = "unix")]
+impl<T: ?Sized> DerefMutHolder<'_, T> {
+  /// Runs `f` on the value, while the memory is still writable.
   #[inline(always)]
-  fn lock_memory(&mut self) -> Result<(), std::io::Error> {
-    if unsafe { libc::mlock(self.inner_ptr as *mut c_void, self.memory_layout.size()) } == 0 {
-      Ok(())
-    } else {
-      Err(std::io::Error::last_os_error())
+  pub fn modify(&mut self, f: impl FnOnce(&mut T)) {
+    f(self)
+  }
+}
+
+impl<T> DerefMutHolder<'_, T> {
+  /// Moves the value out, and replaces it with what `f` makes of it, such as
+  /// to re-encrypt it. The memory stays writable throughout, and the stack is
+  /// cleared afterwards, as far as `zeroize_stack` reaches.
+  ///
+  /// There's no value in place while `f` runs, so the process aborts if it
+  /// panics.
+  pub fn replace_with(mut self, f: impl FnOnce(T) -> T) {
+    /// Panics while unwinding, which aborts.
+    struct AbortOnPanic;
+
+    impl Drop for AbortOnPanic {
+      fn drop(&mut self) {
+        panic!("Panicked in DerefMutHolder::replace_with");
+      }
     }
+
+    let value: *mut T = &mut *self;
+    let abort = AbortOnPanic;
+    unsafe { value.write(f(value.read())) };
+    core::mem::forget(abort);
+    zeroize_stack();
   }
+}
 
-  #[cfg(target_family = "windows")]
-  #[inline(always)]
-  fn lock_memory(&mut self) -> Result<(), std::io::Error> {
-    if unsafe { memoryapi::VirtualLock(self.inner_ptr as *mut c_void, self.memory_layout.size()) }
-       != 0
-    {
-      Ok(())
+// None of these ever touch the value, so they can't leak it.
+impl<T: ?Sized> fmt::Debug for SensitiveData<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SensitiveData")
+     .field("len", &self.memory_layout.size())
+     .field("accessible", &self.is_accessible())
+     .finish()
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for DerefHolder<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DerefHolder")
+     .field("holder", self.holder)
+     .finish()
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for RawBorrow<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RawBorrow")
+     .field("holder", self.borrow.holder)
+     .finish()
+  }
+}
+
+impl<T: ?Sized, U: ?Sized> fmt::Debug for ProjectedHolder<'_, T, U> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ProjectedHolder")
+     .field("holder", self.borrow.holder)
+     .finish()
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for DerefMutHolder<'_, T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DerefMutHolder")
+     .field("holder", self.holder)
+     .finish()
+  }
+}
+
+/// Overwrites `bytes` with zeroes, in a way that can't be optimized away.
+/// `black_box` keeps the compiler from assuming anything about the memory
+/// afterwards, and the fences keep the writes from being reordered with
+/// whatever frees or protects it next.
+fn zeroize(bytes: &mut [u8]) {
+  compiler_fence(Ordering::SeqCst);
+  for byte in bytes.iter_mut() {
+    unsafe { core::ptr::write_volatile(byte, 0) }
+  }
+  fence(Ordering::Release);
+  black_box(bytes);
+}
+
+/// FNV-1a, which is enough to notice corruption, but not to stop anyone able to
+/// write the memory from forging it.
+fn checksum(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+              })
+}
+
+/// What the memory is called where it can be named, for auditing.
+#[cfg(feature = "named-regions")]
+const REGION_NAME: &core::ffi::CStr =
+  match core::ffi::CStr::from_bytes_with_nul(b"sensitive-data\0") {
+    Ok(name) => name,
+    Err(_) => panic!("The region name isn't NUL terminated"),
+  };
+
+/// The number of bytes of stack cleared by `zeroize_stack`.
+const STACK_SCRATCH: usize = 1024;
+
+/// Overwrites the stack just below the caller, where the functions it just
+/// returned from may have left copies or spills of the value. How deep they
+/// went can't be known, so this only covers `STACK_SCRATCH` bytes, and
+/// registers are out of reach entirely. It's only used right after the crate's
+/// own copies, and the closures passed to `with_ref` and `with_mut`.
+#[inline(never)]
+fn zeroize_stack() {
+  let mut scratch = [0u8; STACK_SCRATCH];
+  zeroize(&mut scratch);
+}
+
+/// Allocates memory for `layout` from the allocator in `options`, zeroed if
+/// the pages are locked on fault. Returns null if that fails. Lazy allocations
+/// from the global allocator are left as they are, since they're discarded.
+fn allocate(options: &Options, layout: Layout) -> *mut u8 {
+  #[cfg(feature = "allocator-api")]
+  {
+    let allocation = if options.lock_on_fault {
+      options.allocator.allocate_zeroed(layout)
+    } else {
+      options.allocator.allocate(layout)
+    };
+    allocation.map_or(core::ptr::null_mut(), |ptr| ptr.cast::<u8>().as_ptr())
+  }
+  #[cfg(not(feature = "allocator-api"))]
+  unsafe {
+    if options.lock_on_fault && !options.lazy {
+      alloc::alloc::alloc_zeroed(layout)
     } else {
-      Err(std::io::Error::last_os_error())
+      alloc::alloc::alloc(layout)
     }
   }
+}
 
-  fn new_holder() -> Result<Self, Error> {
-    use std::alloc::alloc;
-    let memory_layout = Self::layout()?;
-    let inner_ptr;
-    unsafe {
-      let allocated = alloc(memory_layout);
-      inner_ptr = allocated as *mut HolderInner<T>;
-    }
-    let mut data = SensitiveData { memory_layout,
-                                   inner_ptr,
-                                   deref_counter: AtomicUsize::new(0) };
-    data.lock_memory()?;
-    Ok(data)
+/// Pads `layout` so that it covers whole pages and starts on a page boundary.
+/// A type aligned to more than a page keeps its own alignment, which is a
+/// multiple of the page size, so the memory can still be protected page by
+/// page.
+fn page_aligned(layout: Layout, page_size: usize) -> Result<Layout, LayoutError> {
+  Ok(layout.align_to(page_size)?.pad_to_align())
+}
+
+/// Returns the layout of the whole allocation for a value with `layout`, and
+/// the offset of the value within it. With `guard_pages` the value is
+/// surrounded by inaccessible pages, so that any overrun faults immediately.
+fn allocation_layout(layout: Layout,
+                     page_size: usize,
+                     guard_pages: bool)
+                     -> Result<(Layout, usize), LayoutError> {
+  if !guard_pages {
+    return Ok((layout, 0));
+  }
+  let guard = Layout::from_size_align(page_size, page_size)?;
+  let (with_front, offset) = guard.extend(layout)?;
+  Ok((with_front.extend(guard)?.0, offset))
+}
+
+impl<T: Sized> SensitiveData<T> {
+  /// The layout of the memory, aligned to `align`, which is at least the page
+  /// size. With `tagged`, there's room for the integrity tag after the value.
+  ///
+  /// Zero sized types are rejected when compiling, since there's nothing to
+  /// protect, and nothing for the memory to be locked around:
+  ///
+  /// ```compile_fail
+  /// # use sensitive_data::SensitiveData;
+  /// let nothing = SensitiveData::new(()).unwrap();
+  /// ```
+  fn layout(align: usize, tagged: bool) -> Result<Layout, LayoutError> {
+    const {
+      assert!(core::mem::size_of::<T>() != 0,
+              "SensitiveData can't hold a zero sized type")
+    };
+    let layout = Layout::new::<T>();
+    let layout = if tagged {
+      layout.extend(Layout::new::<u64>())?.0
+    } else {
+      layout
+    };
+    page_aligned(layout, align)
+  }
+
+  fn new_holder(options: Options) -> Result<Self, Error> {
+    let align = options.protector.page_size().max(options.align);
+    Self::allocate_holder(Self::layout(align, options.integrity)?,
+                          core::mem::size_of::<T>(),
+                          options,
+                          |ptr| ptr as *mut HolderInner<T>)
   }
 
   /// # Safety
   /// This is not guaranteed to produce a valid object
   pub unsafe fn new_zeroed() -> Result<Self, Error> {
-    let mut holder = Self::new_holder()?;
+    let mut holder = Self::new_holder(Options::default())?;
     holder.zeroize_inner();
-    holder.make_inaccessible()
+    holder.make_idle()
+          .expect("Could not make the new SensitiveData inaccessible");
+    Ok(holder)
+  }
+
+  /// Like `new_zeroed`, but without touching the pages. They're discarded and
+  /// then locked as they're first touched, where supported, so they read as
+  /// zeroes without taking up any memory until the value is first written.
+  /// That suits pools of slots which are created up front but mostly unused.
+  /// Elsewhere, and with the `allocator-api` feature, the pages are zeroed
+  /// right away, like with `new_zeroed`.
+  ///
+  /// # Safety
+  /// This is not guaranteed to produce a valid object
+  pub unsafe fn new_lazy() -> Result<Self, Error> {
+    let holder = Self::new_holder(Options { lock_on_fault: true,
+                                            lazy: true,
+                                            ..Options::default() })?;
+    holder.make_idle()
+          .expect("Could not make the new SensitiveData inaccessible");
+    Ok(holder)
+  }
+
+  /// Allocates and locks the memory for a value, without initializing it.
+  /// Unlike `new_zeroed`, the pages aren't touched before the value is written,
+  /// which saves a pass over large buffers that are about to be overwritten.
+  /// Write it through `borrow_mut`, and then call `assume_init`.
+  pub fn new_uninit() -> Result<SensitiveData<MaybeUninit<T>>, Error> {
+    let holder = SensitiveData::<MaybeUninit<T>>::new_holder(Options::default())?;
+    holder.make_idle()
           .expect("Could not make the new SensitiveData inaccessible");
     Ok(holder)
   }
 
+  /// Creates the value in place with `init`, so that it never exists outside of
+  /// the locked memory, unlike with `new`. The memory is writable only while
+  /// `init` runs.
+  ///
+  /// # Safety
+  /// `init` must fully initialize the `MaybeUninit<T>` before it returns, or
+  /// panic. Nothing can check that it did, and the value is assumed to be
+  /// initialized afterwards, so any part left out is undefined behavior once
+  /// the value is used or dropped. That's why this is `unsafe`, unlike `new`.
+  pub unsafe fn new_from_fn(init: impl FnOnce(&mut MaybeUninit<T>)) -> Result<Self, Error> {
+    let holder = Self::new_uninit()?;
+    init(&mut *holder.writable_holder()?);
+    zeroize_stack();
+    Ok(holder.assume_init())
+  }
+
+  /// Moves `t` into memory of its own, which is locked, kept out of core
+  /// dumps where supported, inaccessible except while it's borrowed, and
+  /// zeroized when it's dropped.
+  ///
+  /// On WASM, linear memory can't be locked or protected at all. The value is
+  /// still zeroized when it's dropped, but it's readable at all times and may
+  /// end up wherever the host puts the memory. `is_locked` and `is_accessible`
+  /// report it that way.
   pub fn new(t: T) -> Result<Self, Error> {
-    let holder = Self::new_holder()?;
+    Self::new_with_options(t, Options::default())
+  }
+
+  /// Like `new`, but never locks the memory. It's still protected between
+  /// accesses and zeroized on drop.
+  pub fn new_unlocked(t: T) -> Result<Self, Error> {
+    Self::new_with_options(t,
+                           Options { locking: Locking::Disabled,
+                                     ..Options::default() })
+  }
+
+  /// Like `new`, but falls back to unlocked memory if the limits don't allow
+  /// the memory to be locked. Use `is_locked` to find out which happened.
+  pub fn new_best_effort(t: T) -> Result<Self, Error> {
+    Self::new_with_options(t,
+                           Options { locking: Locking::BestEffort,
+                                     ..Options::default() })
+  }
+
+  /// Like `new`, but leaves the memory readable between borrows instead of
+  /// inaccessible. It's still locked, kept out of core dumps and zeroized on
+  /// drop, but any stray read in the process can see the value. This saves the
+  /// two protection changes per borrow, for values read in a hot loop.
+  pub fn new_relaxed(t: T) -> Result<Self, Error> {
+    Self::new_with_options(t,
+                           Options { idle: Protection::Readable,
+                                     ..Options::default() })
+  }
+
+  /// Like `new`, but aligns the memory to `align` if that's more than a page,
+  /// such as 2 MiB for a huge page. The memory is padded to a multiple of
+  /// `align` too. Fails with `Error::LayoutError` unless `align` is a power of
+  /// two.
+  pub fn new_aligned(t: T, align: usize) -> Result<Self, Error> {
+    Layout::from_size_align(0, align)?;
+    Self::new_with_options(t,
+                           Options { align,
+                                     ..Options::default() })
+  }
+
+  /// Like `new`, but forked children get zeroes in place of the value, where
+  /// supported, while this process keeps it. This is `MADV_WIPEONFORK` on
+  /// Linux 4.14 and later, and silently skipped elsewhere.
+  pub fn new_wipe_on_fork(t: T) -> Result<Self, Error> {
+    Self::new_with_options(t,
+                           Options { wipe_on_fork: true,
+                                     ..Options::default() })
+  }
+
+  /// Like `new`, but overwrites the memory as `policy` says before it's
+  /// zeroized and freed, with random bytes from `getrandom`.
+  #[cfg(feature = "getrandom")]
+  pub fn new_with_wipe_policy(t: T, policy: WipePolicy) -> Result<Self, Error> {
+    let mut holder = Self::new(t)?;
+    holder.wipe = policy;
+    Ok(holder)
+  }
+
+  /// Like `new`, but protects the memory with `protector` instead of the
+  /// operating system. This is required on targets without an operating system.
+  pub fn with_protector(t: T, protector: &'static dyn MemoryProtector) -> Result<Self, Error> {
+    Self::new_with_options(t,
+                           Options { protector,
+                                     ..Options::default() })
+  }
+
+  fn new_with_options(t: T, options: Options) -> Result<Self, Error> {
+    let holder = Self::new_holder(options)?;
     unsafe {
-      std::ptr::write(holder.inner_ptr,
-                      HolderInner { value: t,
-                                    _marker: PhantomPinned })
+      core::ptr::write(holder.inner_ptr,
+                       HolderInner { value: t,
+                                     _marker: PhantomPinned })
     }
-    holder.make_inaccessible()
+    holder.make_idle()
           .expect("Could not make the new SensitiveData inaccessible");
     Ok(holder)
   }
 
-  #[inline(always)]
-  fn zeroize_inner(&mut self) {
-    use std::{mem::zeroed, ptr::write_volatile};
-    unsafe { write_volatile(self.inner_ptr, zeroed()) }
-    fence(Ordering::Release);
+  /// Derives a new secret from this one. The result of `f` is written
+  /// straight into a new allocation with the same protection as this one, and
+  /// `self` is destroyed afterwards.
+  pub fn map<U>(self, f: impl FnOnce(&T) -> U) -> Result<SensitiveData<U>, Error> {
+    let mapped = SensitiveData::<MaybeUninit<U>>::new_holder(Options { integrity: false,
+                                                                       ..self.options() })?;
+    self.with_ref(|value| {
+          mapped.writable_holder()
+                .expect("Could not make SensitiveData writable")
+                .write(f(value));
+        });
+    zeroize_stack();
+    Ok(unsafe { mapped.assume_init() })
   }
 
-  #[cfg(target_family = "unix")]
-  fn make_inaccessible(&self) -> Result<(), err::IoError> {
-    if unsafe {
-      libc::mprotect(self.inner_ptr as *mut c_void,
-                     self.memory_layout.size(),
-                     libc::PROT_NONE)
-    } == 0
-    {
-      Ok(())
-    } else {
-      Err(err::IoError::last_os_error())
-    }
+  /// Moves the value out, and zeroizes and frees the protected memory. It must
+  /// be `Unpin`, since it may have been pinned with `as_pinned_mut`.
+  ///
+  /// **The returned value is not protected in any way.** It lives wherever it's
+  /// moved to, can be swapped out, and isn't cleared when dropped. Only use
+  /// this to hand the value to an API that insists on owning it, and prefer
+  /// `with_ref` or `borrow` whenever a reference will do.
+  pub fn into_inner(self) -> T
+    where T: Unpin
+  {
+    self.try_into_inner()
+        .unwrap_or_else(|(_, e)| panic!("Could not make SensitiveData writable: {:?}", e))
   }
 
-  #[cfg(target_family = "windows")]
-  fn make_inaccessible(&self) -> Result<(), err::IoError> {
-    use std::ptr::addr_of_mut;
-    if unsafe {
-      let mut _old_protect = 0;
-      memoryapi::VirtualProtect(self.inner_ptr as *mut c_void,
-                                self.memory_layout.size(),
-                                winnt::PAGE_NOACCESS,
-                                addr_of_mut!(_old_protect))
-    } != 0
-    {
-      Ok(())
-    } else {
-      Err(err::IoError::last_os_error())
+  /// Like `into_inner`, but hands the `SensitiveData` back untouched along with
+  /// the error if the memory can't be made writable, like `Arc::try_unwrap`.
+  /// The value is still protected then, and can be retried or dropped. It must
+  /// be `Unpin`, like for `into_inner`.
+  // Handing `self` back is the point, however large it is.
+  #[allow(clippy::result_large_err)]
+  pub fn try_into_inner(mut self) -> Result<T, (Self, Error)>
+    where T: Unpin
+  {
+    if self.consumed {
+      return Err((self, Error::Consumed));
+    }
+    if let Err(e) = self.make_writable() {
+      return Err((self, e.into()));
+    }
+    let mut this = ManuallyDrop::new(self);
+    let value = unsafe { core::ptr::read(&(*this.inner_ptr).value) };
+    if let Err((message, e)) = this.free() {
+      drop_failed(message, e);
     }
+    unsafe {
+      core::ptr::drop_in_place(&mut this.state);
+      core::ptr::drop_in_place(&mut this.on_drop);
+    }
+    // The value is moved out anyway, but not through any more copies than the
+    // one returned.
+    zeroize_stack();
+    Ok(value)
   }
 
-  #[cfg(target_family = "unix")]
-  fn make_readable(&self) -> Result<(), err::IoError> {
-    if unsafe {
-      libc::mprotect(self.inner_ptr as *mut c_void,
-                     self.memory_layout.size(),
-                     libc::PROT_READ)
-    } == 0
-    {
-      Ok(())
-    } else {
-      Err(err::IoError::last_os_error())
+  /// Replaces the value in place, without reallocating. The old value is
+  /// dropped and its memory zeroized before `new` is moved in.
+  pub fn replace(&mut self, new: T) -> Result<(), Error> {
+    if self.frozen {
+      return Err(Error::Frozen);
+    }
+    if self.is_poisoned() {
+      return Err(Error::Poisoned);
+    }
+    if self.consumed {
+      return Err(Error::Consumed);
+    }
+    /// Moves the new value in when dropped, so that there's a value in place
+    /// even if dropping the old one panics.
+    struct Replacement<'a, T> {
+      holder: &'a mut SensitiveData<T>,
+      new: ManuallyDrop<T>,
     }
-  }
 
-  #[cfg(target_family = "windows")]
-  fn make_readable(&self) -> Result<(), err::IoError> {
-    use std::ptr::addr_of_mut;
-    if unsafe {
-      let mut _old_protect = 0;
-      memoryapi::VirtualProtect(self.inner_ptr as *mut c_void,
-                                self.memory_layout.size(),
-                                winnt::PAGE_READONLY,
-                                addr_of_mut!(_old_protect))
-    } != 0
-    {
-      Ok(())
-    } else {
-      Err(err::IoError::last_os_error())
+    impl<T> Drop for Replacement<'_, T> {
+      fn drop(&mut self) {
+        self.holder.zeroize_inner();
+        unsafe {
+          core::ptr::write(&mut (*self.holder.inner_ptr).value,
+                           ManuallyDrop::take(&mut self.new))
+        }
+      }
+    }
+
+    self.make_writable()?;
+    #[cfg(feature = "zeroize")]
+    if let Some(before_drop) = self.before_drop {
+      before_drop(unsafe { &mut (*self.inner_ptr).value });
     }
+    let replacement = Replacement { holder: self,
+                                    new: ManuallyDrop::new(new) };
+    unsafe { core::ptr::drop_in_place(&mut (*replacement.holder.inner_ptr).value) };
+    drop(replacement);
+    self.write_tag();
+    self.make_idle()?;
+    Ok(())
   }
 
-  #[cfg(target_family = "unix")]
-  fn make_writable(&mut self) -> Result<(), err::IoError> {
-    if unsafe {
-      libc::mprotect(self.inner_ptr as *mut c_void,
-                     self.memory_layout.size(),
-                     libc::PROT_READ | libc::PROT_WRITE)
-    } == 0
-    {
-      Ok(())
-    } else {
-      Err(err::IoError::last_os_error())
+  /// Reuses the allocation for the next secret, such as in a loop that handles
+  /// one at a time. It's the same as `replace`: the value keeps the same
+  /// physical pages, which stay locked, so it's much cheaper than allocating,
+  /// locking and freeing a new `SensitiveData` every time.
+  #[inline(always)]
+  pub fn reset_with(&mut self, t: T) -> Result<(), Error> {
+    self.replace(t)
+  }
+
+  /// Moves the value out and leaves `T::default()` in its place, like
+  /// `Option::take`, so that the allocation stays locked for the next value.
+  /// The memory is zeroized in between, so nothing of the old value is left
+  /// behind where the default doesn't overwrite it, such as in padding. It must
+  /// be `Unpin`, like for `into_inner`, since it's moved out as well.
+  ///
+  /// ```compile_fail
+  /// # use sensitive_data::SensitiveData;
+  /// # use std::marker::PhantomPinned;
+  /// let mut a = SensitiveData::new((1u8, PhantomPinned)).unwrap();
+  /// let taken = a.take().unwrap();
+  /// ```
+  pub fn take(&mut self) -> Result<T, Error>
+    where T: Default + Unpin
+  {
+    if self.frozen {
+      return Err(Error::Frozen);
     }
+    if self.is_poisoned() {
+      return Err(Error::Poisoned);
+    }
+    if self.consumed {
+      return Err(Error::Consumed);
+    }
+    let default = T::default();
+    self.make_writable()?;
+    let value = unsafe { core::ptr::read(&(*self.inner_ptr).value) };
+    self.zeroize_inner();
+    unsafe { core::ptr::write(&mut (*self.inner_ptr).value, default) };
+    self.write_tag();
+    self.make_idle()?;
+    // The value is moved out anyway, but not through any more copies than the
+    // one returned.
+    zeroize_stack();
+    Ok(value)
   }
+}
 
-  #[cfg(target_family = "windows")]
-  fn make_writable(&self) -> Result<(), err::IoError> {
-    use std::ptr::addr_of_mut;
-    if unsafe {
-      let mut _old_protect = 0;
-      memoryapi::VirtualProtect(self.inner_ptr as *mut c_void,
-                                self.memory_layout.size(),
-                                winnt::PAGE_READWRITE,
-                                addr_of_mut!(_old_protect))
-    } != 0
+impl<T: Clone> Clone for SensitiveData<T> {
+  /// Clones the value straight into a new allocation, with the same protection
+  /// as this one. The clone is frozen if this one is.
+  fn clone(&self) -> Self {
+    let clone = SensitiveData::<MaybeUninit<T>>::new_holder(self.options())
+      .expect("Could not allocate SensitiveData");
+    self.with_ref(|value| {
+          clone.writable_holder()
+               .expect("Could not make SensitiveData writable")
+               .write(value.clone());
+        });
+    zeroize_stack();
+    let mut clone = unsafe { clone.assume_init() };
+    if self.integrity.is_some() {
+      clone.integrity = self.integrity;
+      clone.seal().expect("Could not make SensitiveData writable");
+    }
+    clone.frozen = self.frozen;
+    #[cfg(feature = "zeroize")]
     {
-      Ok(())
-    } else {
-      Err(err::IoError::last_os_error())
+      clone.before_drop = self.before_drop;
+    }
+    #[cfg(feature = "getrandom")]
+    {
+      clone.wipe = self.wipe;
     }
+    clone
   }
+}
 
-  #[inline(always)]
-  pub fn borrow(&self) -> DerefHolder<T> {
-    DerefHolder { holder: self,
-                  changed_permissions: AtomicBool::new(false) }
+impl<T> SensitiveData<MaybeUninit<T>> {
+  /// Treats the value as initialized, in the same memory.
+  ///
+  /// # Safety
+  /// The value must have been initialized.
+  pub unsafe fn assume_init(self) -> SensitiveData<T> {
+    self.cast(|ptr| ptr as *mut HolderInner<T>)
   }
+}
 
-  #[inline(always)]
-  pub fn borrow_mut(&mut self) -> DerefMutHolder<T> {
-    DerefMutHolder { holder: self }
+impl<T: ?Sized> SensitiveData<T> {
+  /// Reinterprets the memory as holding a `U`, with the pointer from `cast`.
+  /// The `integrity` and `before_drop` hooks are cleared, since they're for a
+  /// different type, while `on_drop` is kept.
+  ///
+  /// # Safety
+  /// The memory must hold a valid `U`.
+  unsafe fn cast<U: ?Sized>(self,
+                            cast: impl FnOnce(*mut HolderInner<T>) -> *mut HolderInner<U>)
+                            -> SensitiveData<U> {
+    let this = ManuallyDrop::new(self);
+    SensitiveData { memory_layout: this.memory_layout,
+                    inner_ptr: cast(this.inner_ptr),
+                    value_size: this.value_size,
+                    state: core::ptr::read(&this.state),
+                    idle: this.idle,
+                    locked: this.locked,
+                    frozen: this.frozen,
+                    consumed: this.consumed,
+                    wipe_on_fork: this.wipe_on_fork,
+                    guard_pages: this.guard_pages,
+                    dump_excluded: this.dump_excluded,
+                    protector: this.protector,
+                    #[cfg(feature = "allocator-api")]
+                    allocator: this.allocator,
+                    integrity: None,
+                    #[cfg(feature = "zeroize")]
+                    before_drop: None,
+                    #[cfg(feature = "getrandom")]
+                    wipe: this.wipe,
+                    on_drop: core::ptr::read(&this.on_drop) }
+  }
+}
+
+impl<T: ?Sized> SensitiveData<T> {
+  /// The options for a new allocation protected like this one.
+  fn options(&self) -> Options {
+    Options { locking: if self.locked {
+                Locking::Required
+              } else {
+                Locking::Disabled
+              },
+              lock_on_fault: false,
+              lazy: false,
+              idle: self.idle,
+              wipe_on_fork: self.wipe_on_fork,
+              guard_pages: self.guard_pages,
+              exclude_from_dump: self.dump_excluded,
+              integrity: self.integrity.is_some(),
+              align: self.memory_layout.align(),
+              protector: self.protector,
+              #[cfg(feature = "allocator-api")]
+              allocator: self.allocator }
   }
 
+  /// The start and length of the memory handed to the protector. Both have to
+  /// be page aligned, or the protection would silently apply to the wrong
+  /// pages, or fail outright.
   #[inline(always)]
-  pub fn assert_no_borrows(&mut self) {}
+  fn protected_range(&self) -> (*mut u8, usize) {
+    let (ptr, len) = (self.inner_ptr as *mut u8, self.memory_layout.size());
+    debug_assert_eq!(ptr as usize % self.protector.page_size(),
+                     0,
+                     "SensitiveData isn't page aligned");
+    debug_assert_eq!(len % self.protector.page_size(),
+                     0,
+                     "SensitiveData isn't whole pages");
+    (ptr, len)
+  }
 
   #[inline(always)]
-  pub fn assert_no_mut_borrows(&self) {}
-}
+  fn lock_memory(&mut self, on_fault: bool) -> Result<(), err::IoError> {
+    let (ptr, len) = self.protected_range();
+    if on_fault {
+      self.protector.lock_on_fault(ptr, len)
+    } else {
+      self.protector.lock(ptr, len)
+    }
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  struct SomeTestStruct {
-    a: u8,
+  #[inline(always)]
+  fn exclude_from_dump(&self) -> Result<(), err::IoError> {
+    let (ptr, len) = self.protected_range();
+    self.protector.exclude_from_dump(ptr, len)
   }
 
-  struct WithDestructor {
-    destructor_executed: *mut bool,
+  /// Allocates and locks `memory_layout`, using `make_ptr` to turn the
+  /// allocation into a (possibly fat) pointer to the inner value, which takes
+  /// `value_size` bytes.
+  fn allocate_holder(memory_layout: Layout,
+                     value_size: usize,
+                     options: Options,
+                     make_ptr: impl FnOnce(*mut u8) -> *mut HolderInner<T>)
+                     -> Result<Self, Error> {
+    #[cfg(feature = "std")]
+    process::harden_if_enabled()?;
+    // The guard pages are the only thing that can make it too large here.
+    let (allocation_layout, offset) =
+      allocation_layout(memory_layout,
+                        options.protector.page_size(),
+                        options.guard_pages).map_err(|_| Error::TooLarge)?;
+    let allocation = allocate(&options, allocation_layout);
+    if allocation.is_null() {
+      return Err(Error::AllocError(allocation_layout));
+    }
+    let inner_ptr = make_ptr(unsafe { allocation.add(offset) });
+    // Nothing is written to the memory yet, so if setting it up fails, it's
+    // freed without dropping the value.
+    let mut data =
+      ManuallyDrop::new(SensitiveData { memory_layout,
+                                        inner_ptr,
+                                        value_size,
+                                        state: Box::new(BorrowState {
+                                          deref_counter: AtomicUsize::new(0),
+                                          mut_borrowed: AtomicBool::new(false),
+                                          poisoned: AtomicBool::new(false),
+                                          // Fresh memory from the allocator.
+                                          protection: AtomicU8::new(Protection::Writable as u8),
+                                        }),
+                                        idle: options.idle,
+                                        // These are set as they're applied, so
+                                        // that `free` only undoes what was done.
+                                        locked: false,
+                                        wipe_on_fork: false,
+                                        dump_excluded: false,
+                                        frozen: false,
+                                        consumed: false,
+                                        guard_pages: options.guard_pages,
+                                        protector: options.protector,
+                                        #[cfg(feature = "allocator-api")]
+                                        allocator: options.allocator,
+                                        integrity: None,
+                                        #[cfg(feature = "zeroize")]
+                                        before_drop: None,
+                                        #[cfg(feature = "getrandom")]
+                                        wipe: WipePolicy::ZeroOnce,
+                                        on_drop: None });
+    match data.set_up(options) {
+      Ok(()) => Ok(ManuallyDrop::into_inner(data)),
+      Err(e) => {
+        // The error from setting it up is the one worth returning.
+        let _ = data.free();
+        unsafe { core::ptr::drop_in_place(&mut data.state) };
+        Err(e)
+      }
+    }
   }
 
-  impl Drop for WithDestructor {
-    fn drop(&mut self) {
-      println!("Saved pointer {:p}", self.destructor_executed);
-      unsafe {
-        *self.destructor_executed = true;
+  /// Applies `options` to freshly allocated memory.
+  fn set_up(&mut self, options: Options) -> Result<(), Error> {
+    // Memory from a custom allocator may not be anonymous pages that can be
+    // discarded, so it's zeroed by the allocator instead.
+    if options.lazy && cfg!(not(feature = "allocator-api")) {
+      let (ptr, len) = self.protected_range();
+      if self.protector.discard(ptr, len).is_err() {
+        self.zeroize_inner();
       }
     }
+    if self.guard_pages {
+      self.set_guard_pages(|protector, ptr, len| protector.make_inaccessible(ptr, len))?;
+    }
+    if options.locking != Locking::Disabled {
+      match self.lock_memory(options.lock_on_fault) {
+        Ok(()) => self.locked = true,
+        Err(e) => {
+          // `mlock` may have locked some of the pages before it failed.
+          let (ptr, len) = self.protected_range();
+          let _ = self.protector.unlock(ptr, len);
+          match options.locking {
+            Locking::BestEffort if self.protector.is_lock_limit(&e) => {}
+            Locking::Required if self.protector.is_lock_limit(&e) => {
+              return Err(Error::LockLimitExceeded(e))
+            }
+            _ => return Err(e.into()),
+          }
+        }
+      }
+    }
+    if options.exclude_from_dump {
+      self.exclude_from_dump()?;
+      self.dump_excluded = true;
+    }
+    // The name is only an aid, so older kernels are fine without it.
+    #[cfg(feature = "named-regions")]
+    {
+      let (ptr, len) = self.protected_range();
+      let _ = self.protector.set_name(ptr, len, Some(REGION_NAME));
+    }
+    if options.wipe_on_fork {
+      let (ptr, len) = self.protected_range();
+      self.protector.wipe_on_fork(ptr, len)?;
+      self.wipe_on_fork = true;
+    }
+    Ok(())
   }
 
-  #[test]
-  fn zeroized_when_created() {
-    let a: SensitiveData<SomeTestStruct> = unsafe { SensitiveData::new_zeroed().unwrap() };
-    assert_eq!(a.borrow().a, 0);
+  /// Applies `protect` to the guard pages just before and after the value.
+  fn set_guard_pages(&self,
+                     protect: fn(&dyn MemoryProtector,
+                        *mut u8,
+                        usize) -> Result<(), err::IoError>)
+                     -> Result<(), err::IoError> {
+    let (ptr, len) = self.protected_range();
+    let page_size = self.protector.page_size();
+    protect(self.protector, unsafe { ptr.sub(page_size) }, page_size)?;
+    protect(self.protector, unsafe { ptr.add(len) }, page_size)
   }
 
-  #[test]
-  fn pads_to_page() {
-    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 0 }).unwrap();
-    assert_eq!(a.memory_layout.size(), a.memory_layout.align());
+  /// Registers a reader, making the memory readable if it is the first one.
+  /// Returns only once the memory is readable.
+  fn acquire_read(&self) -> Result<(), err::IoError> {
+    // Making the memory readable would pull it out from under the writer. The
+    // borrow checker prevents this, unless something unsafe aliases the value.
+    debug_assert!(!self.state.mut_borrowed.load(Ordering::Acquire),
+                  "SensitiveData is borrowed while mutably borrowed");
+    loop {
+      match self.state.deref_counter.load(Ordering::Acquire) {
+        count if count & TRANSITIONING != 0 => spin_loop(),
+        0 => {
+          if self.state
+                 .deref_counter
+                 .compare_exchange_weak(0, TRANSITIONING, Ordering::Acquire, Ordering::Relaxed)
+                 .is_ok()
+          {
+            let result = self.make_readable();
+            self.state
+                .deref_counter
+                .store(if result.is_ok() { 1 } else { 0 }, Ordering::Release);
+            return result;
+          }
+        }
+        // Only reachable by forgetting that many holders, but counting on
+        // would leave the memory readable for good, like `Arc` would leak.
+        count if count == TRANSITIONING - 1 => panic!("Too many borrows of SensitiveData"),
+        count => {
+          if self.state
+                 .deref_counter
+                 .compare_exchange_weak(count, count + 1, Ordering::AcqRel, Ordering::Relaxed)
+                 .is_ok()
+          {
+            return Ok(());
+          }
+        }
+      }
+    }
   }
 
-  #[test]
-  fn value_when_created() {
-    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 5 }).unwrap();
-    assert_eq!(a.borrow().a, 5);
+  /// Unregisters a reader, making the memory inaccessible if it was the last
+  /// one.
+  fn release_read(&self) -> Result<(), err::IoError> {
+    loop {
+      match self.state.deref_counter.load(Ordering::Acquire) {
+        count if count & TRANSITIONING != 0 => spin_loop(),
+        1 => {
+          if self.state
+                 .deref_counter
+                 .compare_exchange_weak(1, TRANSITIONING, Ordering::Acquire, Ordering::Relaxed)
+                 .is_ok()
+          {
+            let result = self.make_idle();
+            self.state
+                .deref_counter
+                .store(if result.is_ok() { 0 } else { 1 }, Ordering::Release);
+            return result;
+          }
+        }
+        // Releasing more reads than were acquired is a bug in this crate.
+        // Wrapping around would set `TRANSITIONING`, and hang every reader.
+        0 => {
+          debug_assert!(false, "SensitiveData was released more often than borrowed");
+          return Ok(());
+        }
+        count => {
+          if self.state
+                 .deref_counter
+                 .compare_exchange_weak(count, count - 1, Ordering::AcqRel, Ordering::Relaxed)
+                 .is_ok()
+          {
+            return Ok(());
+          }
+        }
+      }
+    }
   }
 
-  #[test]
+  /// Zeroizes and deallocates the memory, without dropping the value. Unless
+  /// the memory is known to be writable, it's leaked instead, since clearing
+  /// it would fault, and so would the allocator once it handed it out again.
+  /// The same goes for guard pages that can't be made writable again.
+  ///
+  /// It carries on when undoing the rest of the setup fails, so that nothing
+  /// is left half done, and returns what failed first for the caller to
+  /// report.
+  fn free(&mut self) -> Result<(), (&'static str, err::IoError)> {
+    let mut failed = None;
+    let writable = self.state.protection.load(Ordering::Relaxed) == Protection::Writable as u8;
+    let mut leaked = !writable;
+    if writable {
+      #[cfg(feature = "getrandom")]
+      self.wipe.overwrite(self.inner_bytes());
+      self.zeroize_inner();
+    }
+    let (ptr, len) = self.protected_range();
+    if self.dump_excluded {
+      if let Err(e) = self.protector.include_in_dump(ptr, len) {
+        failed.get_or_insert(("Could not include the memory in dumps again", e));
+      }
+    }
+    // The allocator hands the pages out again, to values that aren't secret.
+    #[cfg(feature = "named-regions")]
+    let _ = self.protector.set_name(ptr, len, None);
+    // The allocator may keep the pages, which would then count against the
+    // limit on locked memory for good.
+    if self.locked {
+      if let Err(e) = self.protector.unlock(ptr, len) {
+        failed.get_or_insert(("Could not unlock the memory", e));
+      }
+    }
+    // The allocator hands the pages out again, to values that should survive a
+    // fork.
+    if self.wipe_on_fork {
+      if let Err(e) = self.protector.keep_on_fork(ptr, len) {
+        failed.get_or_insert(("Could not keep the memory on fork again", e));
+      }
+    }
+    if self.guard_pages {
+      if let Err(e) = self.set_guard_pages(|protector, ptr, len| protector.make_writable(ptr, len))
+      {
+        failed.get_or_insert(("Could not make the guard pages writable", e));
+        leaked = true;
+      }
+    }
+    if leaked {
+      return failed.map_or(Ok(()), Err);
+    }
+    #[cfg(test)]
+    tests::record_free(self);
+    let (allocation_layout, offset) =
+      allocation_layout(self.memory_layout,
+                        self.protector.page_size(),
+                        self.guard_pages).expect("The layout was valid when allocating");
+    let allocation = unsafe { (self.inner_ptr as *mut u8).sub(offset) };
+    #[cfg(feature = "allocator-api")]
+    unsafe {
+      self.allocator
+          .deallocate(core::ptr::NonNull::new_unchecked(allocation),
+                      allocation_layout)
+    };
+    #[cfg(not(feature = "allocator-api"))]
+    unsafe {
+      alloc::alloc::dealloc(allocation, allocation_layout)
+    };
+    failed.map_or(Ok(()), Err)
+  }
+
+  /// The whole memory, including any padding.
+  #[inline(always)]
+  fn inner_bytes(&mut self) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(self.inner_ptr as *mut u8, self.memory_layout.size()) }
+  }
+
+  #[inline(always)]
+  fn zeroize_inner(&mut self) {
+    zeroize(self.inner_bytes());
+  }
+
+  /// Sets the protection of the memory with `change`, unless it already is
+  /// `protection`. Changes are serialized by `deref_counter`, by having
+  /// `&mut self`, or by the caller of `borrow_mut_shared`, so relaxed ordering
+  /// is enough for the cached state.
+  fn protect(&self,
+             protection: Protection,
+             change: fn(&dyn MemoryProtector, *mut u8, usize) -> Result<(), err::IoError>)
+             -> Result<(), err::IoError> {
+    if self.state.protection.load(Ordering::Relaxed) == protection as u8 {
+      return Ok(());
+    }
+    let (ptr, len) = self.protected_range();
+    change(self.protector, ptr, len)?;
+    self.state
+        .protection
+        .store(protection as u8, Ordering::Relaxed);
+    Ok(())
+  }
+
+  /// Makes the memory inaccessible, or readable in relaxed mode.
+  #[inline(always)]
+  fn make_idle(&self) -> Result<(), err::IoError> {
+    if self.idle == Protection::Readable {
+      self.make_readable()
+    } else {
+      self.make_inaccessible()
+    }
+  }
+
+  #[inline(always)]
+  fn make_inaccessible(&self) -> Result<(), err::IoError> {
+    self.protect(Protection::Inaccessible, |protector, ptr, len| {
+          protector.make_inaccessible(ptr, len)
+        })
+  }
+
+  #[inline(always)]
+  fn make_readable(&self) -> Result<(), err::IoError> {
+    self.protect(Protection::Readable, |protector, ptr, len| {
+          protector.make_readable(ptr, len)
+        })
+  }
+
+  #[inline(always)]
+  fn make_writable(&mut self) -> Result<(), err::IoError> {
+    self.protect(Protection::Writable, |protector, ptr, len| {
+          protector.make_writable(ptr, len)
+        })
+  }
+
+  /// The integrity tag, in the last bytes of the memory.
+  #[inline(always)]
+  fn tag_ptr(&self) -> *mut u64 {
+    unsafe { (self.inner_ptr as *mut u8).add(self.memory_layout.size() - 8) as *mut u64 }
+  }
+
+  /// Whether the value matches its integrity tag, if it has one. The memory
+  /// must be readable.
+  fn tag_matches(&self) -> bool {
+    match self.integrity {
+      Some(checksum) => {
+        checksum(unsafe { &(*self.inner_ptr).value }) == unsafe { self.tag_ptr().read_volatile() }
+      }
+      None => true,
+    }
+  }
+
+  /// Updates the integrity tag of the value, if it has one. The memory must be
+  /// writable.
+  fn write_tag(&self) {
+    if let Some(checksum) = self.integrity {
+      unsafe {
+        self.tag_ptr()
+            .write_volatile(checksum(&(*self.inner_ptr).value))
+      };
+    }
+  }
+
+  /// Writes the integrity tag of a value that didn't have one.
+  fn seal(&mut self) -> Result<(), err::IoError> {
+    self.make_writable()?;
+    self.write_tag();
+    self.make_idle()
+  }
+
+  /// Whether the value still matches its integrity tag, which catches it being
+  /// changed behind the back of this crate, such as by a bit flip. Always true
+  /// for values created without one, as long as there is a value to check.
+  /// False once it's consumed by `zeroize` or poisoned. Panics if the memory
+  /// can't be made readable.
+  pub fn verify(&self) -> bool {
+    match self.try_borrow() {
+      Ok(_) => true,
+      Err(Error::Corrupted | Error::Consumed | Error::Poisoned) => false,
+      Err(e) => panic!("Could not make SensitiveData readable: {:?}", e),
+    }
+  }
+
+  /// Borrows the value. The memory is made readable on the first dereference
+  /// of the returned holder, and panics if that fails.
+  #[inline(always)]
+  pub fn borrow(&self) -> DerefHolder<'_, T> {
+    DerefHolder { holder: self,
+                  permissions: AtomicU8::new(UNTOUCHED),
+                  _not_send: PhantomData }
+  }
+
+  /// Borrows the value, making the memory readable immediately. Fails with
+  /// `Error::Corrupted` if the integrity tag doesn't match.
+  pub fn try_borrow(&self) -> Result<DerefHolder<'_, T>, Error> {
+    if self.is_poisoned() {
+      return Err(Error::Poisoned);
+    }
+    if self.consumed {
+      return Err(Error::Consumed);
+    }
+    self.acquire_read()?;
+    if !self.tag_matches() {
+      self.release_read()?;
+      return Err(Error::Corrupted);
+    }
+    Ok(DerefHolder { holder: self,
+                     permissions: AtomicU8::new(CHANGED),
+                     _not_send: PhantomData })
+  }
+
+  /// Borrows the value as a raw pointer and a length, such as to hand it to a
+  /// C library. The memory is made readable immediately, and inaccessible
+  /// again when the returned `RawBorrow` is dropped. Panics like `with_ref`.
+  pub fn as_ptr_readable(&self) -> RawBorrow<'_, T> {
+    RawBorrow { borrow: self.try_borrow()
+                            .expect("Could not make SensitiveData readable") }
+  }
+
+  /// Borrows the part of the value that `f` picks, such as one of its fields,
+  /// so that the code it's handed to sees nothing else. The memory is made
+  /// readable immediately, and inaccessible again when the returned
+  /// `ProjectedHolder` is dropped. Panics like `with_ref`.
+  pub fn project<U: ?Sized>(&self, f: impl FnOnce(&T) -> &U) -> ProjectedHolder<'_, T, U> {
+    let borrow = self.try_borrow()
+                     .expect("Could not make SensitiveData readable");
+    let part: *const U = f(&borrow);
+    ProjectedHolder { borrow, part }
+  }
+
+  /// Borrows the value mutably. Panics if the memory can't be made writable,
+  /// or if the value is frozen.
+  ///
+  /// The value must be `Unpin`, since a `&mut T` could move it out after it
+  /// was pinned with `as_pinned_mut`, such as with `mem::swap`. Use
+  /// `as_pinned_mut` for anything else.
+  ///
+  /// ```compile_fail
+  /// # use sensitive_data::SensitiveData;
+  /// # use std::marker::PhantomPinned;
+  /// let mut a = SensitiveData::new((1u8, PhantomPinned)).unwrap();
+  /// let mut b = SensitiveData::new((2u8, PhantomPinned)).unwrap();
+  /// core::mem::swap(&mut *a.borrow_mut(), &mut *b.borrow_mut());
+  /// ```
+  #[inline(always)]
+  pub fn borrow_mut(&mut self) -> DerefMutHolder<'_, T>
+    where T: Unpin
+  {
+    self.try_borrow_mut()
+        .expect("Could not make SensitiveData writable")
+  }
+
+  /// Borrows the value mutably, making the memory writable immediately. Fails
+  /// with `Error::Frozen` if the value is frozen, or `Error::Corrupted` if the
+  /// integrity tag doesn't match. The value must be `Unpin`, like for
+  /// `borrow_mut`, or be borrowed with `try_as_pinned_mut` instead.
+  pub fn try_borrow_mut(&mut self) -> Result<DerefMutHolder<'_, T>, Error>
+    where T: Unpin
+  {
+    self.writable_holder()
+  }
+
+  /// Borrows the value mutably through a shared reference, such as when it's
+  /// in an `Arc`, and writers are already serialized by a lock elsewhere.
+  /// Panics like `borrow_mut`, and the value must be `Unpin` for the same
+  /// reason.
+  ///
+  /// This is easy to get wrong, since nothing but the caller keeps the borrows
+  /// apart. Prefer `borrow_mut`, with a `Mutex<SensitiveData<T>>` if need be.
+  ///
+  /// # Safety
+  /// Nothing else may borrow the value, mutably or not, for as long as the
+  /// returned holder lives.
+  pub unsafe fn borrow_mut_shared(&self) -> DerefMutHolder<'_, T>
+    where T: Unpin
+  {
+    debug_assert_eq!(self.state.deref_counter.load(Ordering::Acquire),
+                     0,
+                     "SensitiveData is mutably borrowed while borrowed");
+    debug_assert!(!self.state.mut_borrowed.load(Ordering::Acquire),
+                  "SensitiveData is mutably borrowed twice");
+    self.writable_holder()
+        .expect("Could not make SensitiveData writable")
+  }
+
+  /// Makes the memory writable, and hands out a holder for it. The callers
+  /// make sure that nothing else borrows the value meanwhile.
+  fn writable_holder(&self) -> Result<DerefMutHolder<'_, T>, Error> {
+    if self.frozen {
+      return Err(Error::Frozen);
+    }
+    if self.is_poisoned() {
+      return Err(Error::Poisoned);
+    }
+    if self.consumed {
+      return Err(Error::Consumed);
+    }
+    self.protect(Protection::Writable, |protector, ptr, len| {
+          protector.make_writable(ptr, len)
+        })?;
+    if !self.tag_matches() {
+      self.make_idle()?;
+      return Err(Error::Corrupted);
+    }
+    self.state.mut_borrowed.store(true, Ordering::Release);
+    Ok(DerefMutHolder { holder: self,
+                        _exclusive: PhantomData,
+                        _not_send: PhantomData })
+  }
+
+  /// Like `borrow`, but pinned. The value never moves for as long as it lives,
+  /// so it may be self referential.
+  #[inline(always)]
+  pub fn as_pinned(&self) -> Pin<DerefHolder<'_, T>> {
+    unsafe { Pin::new_unchecked(self.borrow()) }
+  }
+
+  /// Like `borrow_mut`, but pinned. The value never moves for as long as it
+  /// lives, so it may be self referential, and this is the only way to borrow
+  /// it mutably if it isn't `Unpin`.
+  #[inline(always)]
+  pub fn as_pinned_mut(&mut self) -> Pin<DerefMutHolder<'_, T>> {
+    self.try_as_pinned_mut()
+        .expect("Could not make SensitiveData writable")
+  }
+
+  /// Like `try_borrow_mut`, but pinned, like `as_pinned_mut`.
+  pub fn try_as_pinned_mut(&mut self) -> Result<Pin<DerefMutHolder<'_, T>>, Error> {
+    Ok(unsafe { Pin::new_unchecked(self.writable_holder()?) })
+  }
+
+  /// Runs `f` with the value readable, and makes it inaccessible again before
+  /// returning, even if `f` panics. Unlike a `DerefHolder`, this can be used
+  /// in futures that have to be `Send`. The stack `f` ran on is cleared
+  /// afterwards, as far as `zeroize_stack` reaches. With the `block-signals`
+  /// feature, the signals from `block_signals_during_access` are held back
+  /// until then.
+  pub fn with_ref<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+    #[cfg(all(feature = "block-signals", target_family = "unix"))]
+    let _blocked = process::block_signals();
+    let result = f(&self.try_borrow()
+                        .expect("Could not make SensitiveData readable"));
+    zeroize_stack();
+    result
+  }
+
+  /// Runs `f` with the value writable, and makes it inaccessible again before
+  /// returning, even if `f` panics. The stack is cleared afterwards, and
+  /// signals are held back, like in `with_ref`. The value must be `Unpin`, like
+  /// for `borrow_mut`, since `f` gets a `&mut T`.
+  pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R
+    where T: Unpin
+  {
+    #[cfg(all(feature = "block-signals", target_family = "unix"))]
+    let _blocked = process::block_signals();
+    let result = f(&mut self.borrow_mut());
+    zeroize_stack();
+    result
+  }
+
+  /// Makes the value read only, for good. Mutable borrows fail from here on,
+  /// so the memory is never writable again until the value is dropped, either
+  /// with the `SensitiveData` or early by `zeroize`.
+  #[inline(always)]
+  pub fn freeze(&mut self) {
+    self.frozen = true;
+  }
+
+  /// Drops the value and zeroizes its memory right away, rather than when the
+  /// `SensitiveData` is dropped, for when the secret is known to be done with
+  /// early. Borrowing fails with `Error::Consumed` from then on, or panics
+  /// where it can't fail. The memory itself is kept, locked and inaccessible,
+  /// until the `SensitiveData` is dropped. Panics if the memory can't be made
+  /// writable.
+  pub fn zeroize(&mut self) {
+    if self.consumed {
+      return;
+    }
+    self.make_writable()
+        .expect("Could not make SensitiveData writable");
+    // Set first, so that the value isn't dropped again if its destructor
+    // panics.
+    self.consumed = true;
+    #[cfg(feature = "zeroize")]
+    if let Some(before_drop) = self.before_drop {
+      before_drop(unsafe { &mut (*self.inner_ptr).value });
+    }
+    unsafe { core::ptr::drop_in_place(self.inner_ptr) };
+    #[cfg(feature = "getrandom")]
+    self.wipe.overwrite(self.inner_bytes());
+    self.zeroize_inner();
+    self.make_inaccessible()
+        .expect("Could not make SensitiveData inaccessible");
+  }
+
+  /// Whether the value was dropped early by `zeroize`.
+  #[inline(always)]
+  pub fn is_consumed(&self) -> bool {
+    self.consumed
+  }
+
+  /// Runs `f` once the value has been dropped and its memory zeroized and
+  /// freed, such as to log it or count the live secrets. `f` only gets the size
+  /// of the value, never the value itself. It replaces any earlier hook, and
+  /// doesn't run when the value is moved out by `into_inner`, or for a clone.
+  pub fn on_drop(&mut self, f: impl FnOnce(usize) + Send + 'static) {
+    self.on_drop = Some(Box::new(f));
+  }
+
+  /// Whether the memory couldn't be protected again after a borrow. Borrowing
+  /// fails with `Error::Poisoned` from then on, or panics where it can't fail,
+  /// since the value may be exposed. It's still zeroized when dropped.
+  #[inline(always)]
+  pub fn is_poisoned(&self) -> bool {
+    self.state.poisoned.load(Ordering::Acquire)
+  }
+
+  #[inline(always)]
+  fn poison(&self) {
+    self.state.poisoned.store(true, Ordering::Release);
+  }
+
+  /// The protection the memory was last given through the protector, such as
+  /// for a test or a monitor to check that it's inaccessible at rest. It's
+  /// what this crate asked for, rather than what the OS reports, and another
+  /// thread may change it right after.
+  pub fn protection(&self) -> Protection {
+    match self.state.protection.load(Ordering::Relaxed) {
+      p if p == Protection::Inaccessible as u8 => Protection::Inaccessible,
+      p if p == Protection::Readable as u8 => Protection::Readable,
+      _ => Protection::Writable,
+    }
+  }
+
+  /// Whether the memory can be read or written right now, like `protection`.
+  #[inline(always)]
+  pub fn is_accessible(&self) -> bool {
+    self.protection() != Protection::Inaccessible
+  }
+
+  #[inline(always)]
+  pub fn is_frozen(&self) -> bool {
+    self.frozen
+  }
+
+  /// Whether the memory is locked, and therefore kept out of swap. It never is
+  /// on WASM, which has nothing to lock.
+  #[inline(always)]
+  pub fn is_locked(&self) -> bool {
+    self.locked
+  }
+
+  /// Asks the operating system whether every page of the memory is locked
+  /// right now, for deployments that don't want to take `is_locked` on trust,
+  /// such as in containers where locking may silently do nothing. This reads
+  /// `/proc/self/smaps` on Linux, which needs the `std` feature, and uses
+  /// `QueryWorkingSetEx` on Windows. Fails where it can't be found out.
+  pub fn verify_locked(&self) -> Result<bool, Error> {
+    let (ptr, len) = self.protected_range();
+    Ok(self.protector.verify_locked(ptr, len)?)
+  }
+
+  /// The size of the protected memory, which is always whole pages. This is
+  /// what counts against the limit on locked memory, when `is_locked`.
+  #[inline(always)]
+  pub fn locked_size(&self) -> usize {
+    self.memory_layout.size()
+  }
+
+  /// The number of pages in `locked_size`.
+  #[inline(always)]
+  pub fn page_count(&self) -> usize {
+    self.memory_layout.size() / self.protector.page_size()
+  }
+
+  /// Panics in debug builds if the value is still borrowed, which can only
+  /// happen if a `DerefHolder` was leaked.
+  #[inline(always)]
+  pub fn assert_no_borrows(&mut self) {
+    debug_assert_eq!(self.state.deref_counter.load(Ordering::Acquire),
+                     0,
+                     "SensitiveData is still borrowed");
+  }
+
+  /// Panics in debug builds if the value is still mutably borrowed, which can
+  /// only happen if a `DerefMutHolder` was leaked.
+  #[inline(always)]
+  pub fn assert_no_mut_borrows(&self) {
+    debug_assert!(!self.state.mut_borrowed.load(Ordering::Acquire),
+                  "SensitiveData is still mutably borrowed");
+  }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> SensitiveData<T> {
+  /// Runs `f` with the bytes of the value, such as to feed them to a hasher.
+  /// The memory is readable only while `f` runs.
+  pub fn expose_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+    self.with_ref(|value| f(value.as_ref()))
+  }
+
+  /// Compares the bytes of two secrets in constant time. Both are readable only
+  /// for the duration of the comparison.
+  pub fn ct_eq(&self, other: &SensitiveData<T>) -> CtBool {
+    self.with_ref(|a| other.with_ref(|b| ct::eq(a.as_ref(), b.as_ref())))
+  }
+
+  /// Compares the bytes of the secret with `candidate` in constant time, such
+  /// as to verify a password. A length mismatch is found without returning
+  /// early, so the time only depends on the lengths.
+  pub fn ct_eq_slice(&self, candidate: &[u8]) -> CtBool {
+    self.with_ref(|secret| ct::eq_padded(secret.as_ref(), candidate))
+  }
+
+  /// Compares the bytes of two secrets lexicographically in constant time, such
+  /// as to keep them in a sorted container. Both are readable only for the
+  /// duration of the comparison. Secrets of different lengths are compared
+  /// like slices are, but the lengths themselves can't be hidden.
+  pub fn ct_cmp(&self, other: &SensitiveData<T>) -> core::cmp::Ordering {
+    self.with_ref(|a| other.with_ref(|b| ct::cmp(a.as_ref(), b.as_ref())))
+  }
+
+  /// Feeds the bytes to `state` in a single write, so that a newtype can
+  /// implement `Hash` with it. The memory is readable only for the duration.
+  ///
+  /// `SensitiveData` doesn't implement `Hash` itself, since a hash can only be
+  /// had by handing the secret to code that this crate can't vouch for. A
+  /// hasher which doesn't run in constant time leaks the bytes through timing,
+  /// and the hash itself, such as where the key lands in a `HashMap`, tells
+  /// something about them no matter what. `DefaultHasher` only depends on the
+  /// length for its timing. Pair this with `ct_eq` for `PartialEq`.
+  ///
+  /// ```
+  /// # use sensitive_data::SensitiveData;
+  /// # use std::{collections::HashMap, hash::{Hash, Hasher}};
+  /// struct Key(SensitiveData<[u8; 32]>);
+  ///
+  /// impl Hash for Key {
+  ///   fn hash<H: Hasher>(&self, state: &mut H) {
+  ///     self.0.hash_ct(state)
+  ///   }
+  /// }
+  /// # impl PartialEq for Key { fn eq(&self, other: &Self) -> bool { self.0.ct_eq(&other.0).into_bool() } }
+  /// # impl Eq for Key {}
+  /// let mut map = HashMap::new();
+  /// map.insert(Key(SensitiveData::new([1; 32]).unwrap()), "one");
+  /// ```
+  ///
+  /// ```compile_fail
+  /// # use sensitive_data::SensitiveData;
+  /// # use std::collections::HashSet;
+  /// let mut set = HashSet::new();
+  /// set.insert(SensitiveData::new([1u8; 32]).unwrap());
+  /// ```
+  pub fn hash_ct<H: Hasher>(&self, state: &mut H) {
+    self.expose_bytes(|bytes| state.write(bytes))
+  }
+
+  /// Hashes the bytes with `D`, such as `sha2::Sha256`, and returns the hash.
+  /// The memory is readable only for the duration, and the hasher's state,
+  /// which is on the stack, is cleared afterwards as far as `zeroize_stack`
+  /// reaches.
+  #[cfg(feature = "digest")]
+  pub fn digest<D: digest::Digest>(&self) -> digest::Output<D> {
+    self.expose_bytes(|bytes| D::digest(bytes))
+  }
+
+  /// Copies the bytes into `dest`, which must be exactly as long, such as to
+  /// move a derived key between two secrets. Both are accessible only for the
+  /// duration of the copy, which goes straight from one to the other. Fails
+  /// with `Error::LengthMismatch`, leaving `dest` untouched, if the lengths
+  /// differ.
+  pub fn copy_to(&self, dest: &mut SensitiveBytes) -> Result<(), Error> {
+    let source = self.try_borrow()?;
+    let source = (*source).as_ref();
+    if source.len() != dest.len() {
+      return Err(Error::LengthMismatch);
+    }
+    dest.try_borrow_mut()?.copy_from_slice(source);
+    Ok(())
+  }
+}
+
+impl<T: AsRef<[u8]>> SensitiveData<T> {
+  /// Like `new`, but keeps a checksum of the bytes next to the value, in the
+  /// same memory. It's checked on every borrow, which fails or panics if the
+  /// value was changed behind the back of this crate, such as by a bit flip.
+  /// The checksum is no MAC, so it doesn't stop anyone who can write to the
+  /// memory from forging it.
+  pub fn new_with_integrity(t: T) -> Result<Self, Error> {
+    let mut holder = Self::new_with_options(t,
+                                            Options { integrity: true,
+                                                      ..Options::default() })?;
+    holder.integrity = Some(|value| checksum(value.as_ref()));
+    holder.seal()?;
+    Ok(holder)
+  }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: zeroize::Zeroize> SensitiveData<T> {
+  /// Like `new`, but also runs `Zeroize::zeroize` on the value before it's
+  /// dropped. Use this for values that own memory outside of the locked
+  /// region, such as the buffer of a `Vec`, which would otherwise be freed
+  /// without being cleared.
+  pub fn new_zeroizing(t: T) -> Result<Self, Error> {
+    let mut holder = Self::new(t)?;
+    holder.before_drop = Some(|value| value.zeroize());
+    Ok(holder)
+  }
+}
+
+// The locked region itself is always cleared on drop.
+#[cfg(feature = "zeroize")]
+impl<T: ?Sized> zeroize::ZeroizeOnDrop for SensitiveData<T> {}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+
+  use super::*;
+
+  /// The page size of the default protector.
+  pub(crate) fn page_size() -> usize {
+    protector::default_protector().page_size()
+  }
+
+  /// Fails every allocation of a terabyte or more, so that running out of
+  /// memory can be tested without actually doing so.
+  struct FailingAllocator;
+
+  #[cfg(target_pointer_width = "64")]
+  const HUGE: usize = 1 << 40;
+  /// Nothing that large could be allocated on smaller targets anyway.
+  #[cfg(not(target_pointer_width = "64"))]
+  const HUGE: usize = isize::MAX as usize;
+
+  unsafe impl std::alloc::GlobalAlloc for FailingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+      if layout.size() >= HUGE {
+        core::ptr::null_mut()
+      } else {
+        std::alloc::System.alloc(layout)
+      }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+      std::alloc::System.dealloc(ptr, layout)
+    }
+  }
+
+  #[global_allocator]
+  static ALLOCATOR: FailingAllocator = FailingAllocator;
+
+  std::thread_local! {
+    /// The address of every allocation freed by this thread, and whether it was
+    /// all zeroes right before being deallocated.
+    pub(crate) static FREED: RefCell<Vec<(usize, bool)>> = const { RefCell::new(Vec::new()) };
+  }
+
+  pub(crate) fn record_free<T: ?Sized>(data: &SensitiveData<T>) {
+    let ptr = data.inner_ptr as *const u8;
+    let memory = unsafe { std::slice::from_raw_parts(ptr, data.memory_layout.size()) };
+    FREED.with(|freed| {
+           freed.borrow_mut()
+                .push((ptr as usize, memory.iter().all(|b| *b == 0)))
+         });
+  }
+
+  /// Whether the allocation at `address` has been freed by this thread, and was
+  /// zeroized first.
+  pub(crate) fn freed_zeroized(address: usize) -> bool {
+    FREED.with(|freed| freed.borrow().contains(&(address, true)))
+  }
+  struct SomeTestStruct {
+    a: u8,
+  }
+
+  struct WithDestructor {
+    destructor_executed: *mut bool,
+  }
+
+  pub(crate) struct CountsDrops {
+    pub(crate) drops: *mut usize,
+  }
+
+  impl Drop for CountsDrops {
+    fn drop(&mut self) {
+      unsafe { *self.drops += 1 };
+    }
+  }
+
+  impl Drop for WithDestructor {
+    fn drop(&mut self) {
+      println!("Saved pointer {:p}", self.destructor_executed);
+      unsafe {
+        *self.destructor_executed = true;
+      }
+    }
+  }
+
+  #[test]
+  fn zeroized_when_created() {
+    let a: SensitiveData<SomeTestStruct> = unsafe { SensitiveData::new_zeroed().unwrap() };
+    assert_eq!(a.borrow().a, 0);
+  }
+
+  #[test]
+  fn pads_to_page() {
+    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 0 }).unwrap();
+    assert_eq!(a.memory_layout.size(), a.memory_layout.align());
+  }
+
+  #[test]
+  fn aligned_beyond_page() {
+    #[repr(align(8192))]
+    struct OverAligned([u8; 16]);
+    let align = page_size().max(8192);
+    let a = SensitiveData::new(OverAligned([1; 16])).unwrap();
+    assert_eq!(a.memory_layout.align(), align);
+    assert_eq!(a.memory_layout.size(), align);
+    assert_eq!(a.inner_ptr as usize % 8192, 0);
+    assert_eq!(a.borrow().0, [1; 16]);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    let b = SensitiveDataBuilder::new().guard_pages(true)
+                                       .build(OverAligned([2; 16]))
+                                       .unwrap();
+    assert_eq!(b.inner_ptr as usize % 8192, 0);
+    assert_eq!(b.borrow().0, [2; 16]);
+    #[cfg(all(target_family = "unix", not(miri)))]
+    {
+      let start = b.inner_ptr as usize;
+      assert!(is_page_inaccessible(start - page_size()));
+      assert!(is_page_inaccessible(start + b.memory_layout.size()));
+    }
+  }
+
+  #[test]
+  fn aligned() {
+    const HUGE_PAGE: usize = 2 << 20;
+    let a = SensitiveData::new_aligned([1u8; 32], HUGE_PAGE).unwrap();
+    assert_eq!(a.memory_layout.align(), HUGE_PAGE);
+    assert_eq!(a.locked_size(), HUGE_PAGE);
+    assert_eq!(a.inner_ptr as usize % HUGE_PAGE, 0);
+    assert_eq!(*a.borrow(), [1; 32]);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    assert_eq!(a.clone().memory_layout.align(), HUGE_PAGE);
+    let b = SensitiveData::new_aligned([1u8; 32], 64).unwrap();
+    assert_eq!(b.memory_layout.align(), page_size());
+    for align in [0, 3, 3 * page_size()] {
+      assert!(matches!(SensitiveData::new_aligned([1u8; 32], align),
+                       Err(Error::LayoutError(_))));
+    }
+  }
+
+  #[test]
+  fn locked_size() {
+    let a: SensitiveData<[u8; 1]> = SensitiveData::new([1]).unwrap();
+    assert_eq!(a.locked_size(), page_size());
+    assert_eq!(a.page_count(), 1);
+    let b = SensitiveBytes::with_len(page_size() + 1).unwrap();
+    assert_eq!(b.locked_size(), 2 * page_size());
+    assert_eq!(b.page_count(), 2);
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn every_page_protected() {
+    let mut a: SensitiveData<[u8; 65537]> = SensitiveData::new([1; 65537]).unwrap();
+    let pages = a.page_count();
+    assert!(pages > 1);
+    assert_eq!(inaccessible_pages(&a), vec![true; pages]);
+    a.with_ref(|_| assert_eq!(inaccessible_pages(&a), vec![false; pages]));
+    assert_eq!(inaccessible_pages(&a), vec![true; pages]);
+    a.with_mut(|value| value[65536] = 2);
+    assert_eq!(inaccessible_pages(&a), vec![true; pages]);
+  }
+
+  #[test]
+  fn without_zero_bit_pattern() {
+    use core::num::NonZeroU32;
+    let a = SensitiveData::new(NonZeroU32::new(7).unwrap()).unwrap();
+    assert_eq!(a.borrow().get(), 7);
+    let b = SensitiveData::new(core::cmp::Ordering::Greater).unwrap();
+    assert_eq!(*b.borrow(), core::cmp::Ordering::Greater);
+    let address = a.inner_ptr as usize;
+    drop(a);
+    assert!(freed_zeroized(address));
+  }
+
+  #[test]
+  fn written_when_uninit() {
+    let mut a = SensitiveData::<[u8; 32]>::new_uninit().unwrap();
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    a.borrow_mut().write([9; 32]);
+    let a = unsafe { a.assume_init() };
+    assert!(a.is_locked());
+    assert_eq!(*a.borrow(), [9; 32]);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn created_in_place() {
+    let a = unsafe {
+              SensitiveData::<[u8; 64]>::new_from_fn(|value| {
+                let bytes = value.as_mut_ptr() as *mut u8;
+                for i in 0..64 {
+                  bytes.add(i).write(i as u8);
+                }
+              })
+            }.unwrap();
+    assert!(a.borrow().iter().enumerate().all(|(i, b)| *b == i as u8));
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn nested() {
+    let mut a = SensitiveData::new(SensitiveData::new([1u8; 32]).unwrap()).unwrap();
+    a.borrow_mut().borrow_mut()[0] = 2;
+    assert_eq!(a.borrow().borrow()[0], 2);
+    let outer = a.inner_ptr as usize;
+    let inner = a.with_mut(|inner| inner.inner_ptr as usize);
+    drop(a);
+    assert!(freed_zeroized(inner));
+    assert!(freed_zeroized(outer));
+  }
+
+  #[test]
+  fn value_when_created() {
+    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 5 }).unwrap();
+    assert_eq!(a.borrow().a, 5);
+  }
+
+  #[test]
   fn destructor_executed() {
     let mut a: SensitiveData<WithDestructor> = unsafe { SensitiveData::new_zeroed().unwrap() };
     let mut destructor_executed = false;
-    let ptr: &mut bool = &mut destructor_executed;
-    println!("Real pointer {:p}", ptr);
+    let ptr: &mut bool = &mut destructor_executed;
+    println!("Real pointer {:p}", ptr);
+    {
+      a.borrow_mut().destructor_executed = ptr as *mut bool;
+      println!("Borrowed pointer {:p}", a.borrow().destructor_executed);
+    }
+    assert!(!*ptr);
+    drop(a);
+    assert!(*ptr);
+  }
+  /// Returns the `key` line of `/proc/self/smaps` for the mapping that
+  /// contains `address`.
+  #[cfg(all(target_os = "linux", not(miri)))]
+  fn smaps_entry(address: usize, key: &str) -> String {
+    let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+    let mut in_mapping = false;
+    for line in smaps.lines() {
+      let first = line.split_whitespace().next().unwrap_or("");
+      if let Some((start, end)) = first.split_once('-') {
+        if let (Ok(start), Ok(end)) =
+          (usize::from_str_radix(start, 16), usize::from_str_radix(end, 16))
+        {
+          in_mapping = (start..end).contains(&address);
+          continue;
+        }
+      }
+      if in_mapping {
+        if let Some(value) = line.strip_prefix(key)
+                                 .and_then(|rest| rest.strip_prefix(':'))
+        {
+          return value.trim().to_string();
+        }
+      }
+    }
+    panic!("No mapping found for {:#x}", address);
+  }
+
+  /// Returns the `VmFlags` of the mapping that contains `address`.
+  #[cfg(all(target_os = "linux", not(miri)))]
+  pub(crate) fn vm_flags(address: usize) -> Vec<String> {
+    smaps_entry(address, "VmFlags").split_whitespace()
+                                   .map(String::from)
+                                   .collect()
+  }
+
+  /// Returns how many bytes of the mapping that contains `address` are
+  /// resident.
+  #[cfg(all(target_os = "linux", not(miri), not(feature = "allocator-api")))]
+  fn resident(address: usize) -> usize {
+    let rss = smaps_entry(address, "Rss");
+    rss.strip_suffix(" kB").unwrap().parse::<usize>().unwrap() * 1024
+  }
+
+  #[cfg(all(target_os = "linux", not(miri), not(feature = "allocator-api")))]
+  #[test]
+  fn lazy_pages_untouched_until_written() {
+    let mut a = unsafe { SensitiveData::<[u8; 64 << 12]>::new_lazy() }.unwrap();
+    let address = a.inner_ptr as usize;
+    assert!(a.is_locked());
+    assert!(vm_flags(address).iter().any(|flag| flag == "lf"));
+    assert_eq!(resident(address), 0);
+    assert!(a.borrow().iter().all(|b| *b == 0));
+    assert_eq!(resident(address), 0);
+    a.borrow_mut()[page_size()] = 1;
+    assert_eq!(resident(address), page_size());
+    assert_eq!(a.borrow().iter().map(|b| usize::from(*b)).sum::<usize>(), 1);
+  }
+
+  #[cfg(all(target_os = "linux", not(miri)))]
+  #[test]
+  fn excluded_from_core_dumps() {
+    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 0 }).unwrap();
+    assert!(vm_flags(a.inner_ptr as usize).iter()
+                                          .any(|flag| flag == "dd"));
+  }
+
+  #[cfg(all(target_os = "linux", not(miri)))]
+  pub(crate) fn is_page_inaccessible(address: usize) -> bool {
+    !vm_flags(address).iter()
+                      .any(|flag| flag == "rd" || flag == "wr")
+  }
+
+  /// Without `/proc` to ask, try to read the memory and see what happens.
+  #[cfg(all(target_family = "unix", not(target_os = "linux"), not(miri)))]
+  pub(crate) fn is_page_inaccessible(address: usize) -> bool {
+    crashes_in_child(|| unsafe {
+      (address as *const u8).read_volatile();
+    })
+  }
+
+  /// Returns whether each page of `data` is inaccessible.
+  #[cfg(all(target_family = "unix", not(miri)))]
+  pub(crate) fn inaccessible_pages<T: ?Sized>(data: &SensitiveData<T>) -> Vec<bool> {
+    let start = data.inner_ptr as *mut u8 as usize;
+    (0..data.page_count()).map(|page| is_page_inaccessible(start + page * page_size()))
+                          .collect()
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  pub(crate) fn is_inaccessible<T: ?Sized>(data: &SensitiveData<T>) -> bool {
+    inaccessible_pages(data).into_iter()
+                            .all(|inaccessible| inaccessible)
+  }
+
+  /// Nothing is really protected under Miri, so settle for what the protector
+  /// was last told.
+  #[cfg(miri)]
+  pub(crate) fn is_inaccessible<T: ?Sized>(data: &SensitiveData<T>) -> bool {
+    !data.is_accessible()
+  }
+
+  #[test]
+  fn accessibility_reported() {
+    let mut a = SensitiveData::new([1u8; 32]).unwrap();
+    assert!(!a.is_accessible());
+    {
+      let b = a.try_borrow().unwrap();
+      assert_eq!(a.protection(), Protection::Readable);
+      assert_eq!(b[0], 1);
+    }
+    assert_eq!(a.protection(), Protection::Inaccessible);
+    a.with_mut(|value| {
+       value[0] = 2;
+     });
+    assert!(!a.is_accessible());
+    {
+      let _b = a.borrow_mut();
+    }
+    assert!(!a.is_accessible());
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    let relaxed = SensitiveData::new_relaxed([1u8; 32]).unwrap();
+    assert_eq!(relaxed.protection(), Protection::Readable);
+  }
+
+  #[test]
+  fn with_ref_and_with_mut() {
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    a.with_mut(|value| value.a = 2);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    assert_eq!(a.with_ref(|value| value.a), 2);
+    #[cfg(target_family = "unix")]
+    {
+      assert!(is_inaccessible(&a));
+      a.with_ref(|_| assert!(!is_inaccessible(&a)));
+    }
+  }
+
+  #[test]
+  fn with_ref_and_with_mut_restore_on_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    assert!(catch_unwind(AssertUnwindSafe(|| a.with_ref(|_| panic!("Inside with_ref")))).is_err());
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    assert!(catch_unwind(AssertUnwindSafe(|| a.with_mut(|_| panic!("Inside with_mut")))).is_err());
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    assert_eq!(a.with_ref(|value| value.a), 1);
+  }
+
+  #[test]
+  fn indexed() {
+    fn first<I: Index<usize, Output = u8>>(indexed: &I) -> u8 {
+      indexed[0]
+    }
+    let mut a = SensitiveData::new([0u8; 32]).unwrap();
+    {
+      let mut key = a.borrow_mut();
+      key[0..4].copy_from_slice(&[1, 2, 3, 4]);
+      key[31] = 5;
+      assert_eq!(first(&key), 1);
+    }
+    let key = a.borrow();
+    assert_eq!(key[0..4], [1, 2, 3, 4]);
+    assert_eq!(key[28..], [0, 0, 0, 5]);
+    assert_eq!(first(&key), 1);
+  }
+
+  #[test]
+  fn borrows_counted() {
+    let a = SensitiveData::new([1u8; 32]).unwrap();
+    let counter = || a.state.deref_counter.load(Ordering::Acquire);
+    {
+      let never_dereferenced = a.borrow();
+      assert_eq!(counter(), 0);
+      #[cfg(target_family = "unix")]
+      assert!(is_inaccessible(&a));
+      drop(never_dereferenced);
+      assert_eq!(counter(), 0);
+      #[cfg(target_family = "unix")]
+      assert!(is_inaccessible(&a));
+    }
+    {
+      let once = a.borrow();
+      assert_eq!(once[0] + once[1], 2);
+      assert_eq!(counter(), 1);
+      assert_eq!(once[2], 1);
+      assert_eq!(counter(), 1);
+    }
+    assert_eq!(counter(), 0);
+    {
+      let (first, second) = (a.borrow(), a.borrow());
+      assert_eq!(first[0] + second[0], 2);
+      assert_eq!(counter(), 2);
+      drop(first);
+      assert_eq!(counter(), 1);
+      #[cfg(target_family = "unix")]
+      assert!(!is_inaccessible(&a));
+      assert_eq!(second[0], 1);
+    }
+    assert_eq!(counter(), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  /// Leaves a secret in its stack frame, and returns where it was.
+  #[cfg(not(miri))]
+  #[inline(never)]
+  fn leave_on_stack() -> usize {
+    let secret = black_box([0xa5u8; 64]);
+    black_box(&secret).as_ptr() as usize
+  }
+
+  // Reading the stack of a returned function is beyond what Miri allows.
+  #[cfg(not(miri))]
+  #[test]
+  fn stack_zeroized() {
+    let address = leave_on_stack();
+    zeroize_stack();
+    let left = unsafe { core::ptr::read_volatile(address as *const [u8; 64]) };
+    assert_ne!(left, [0xa5; 64]);
+  }
+
+  #[test]
+  fn expose_bytes_to_hasher() {
+    use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+    let key = [0x5a; 32];
+    let mut expected = DefaultHasher::new();
+    expected.write(&key);
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new(key).unwrap();
+    let mut hasher = DefaultHasher::new();
+    a.expose_bytes(|bytes| {
+       assert_eq!(bytes.len(), 32);
+       hasher.write(&bytes[..16]);
+       hasher.write(&bytes[16..]);
+     });
+    assert_eq!(hasher.finish(), expected.finish());
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn constant_time_equality() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([1; 32]).unwrap();
+    let b: SensitiveData<[u8; 32]> = SensitiveData::new([1; 32]).unwrap();
+    let mut last_differs = [1; 32];
+    last_differs[31] = 2;
+    let c: SensitiveData<[u8; 32]> = SensitiveData::new(last_differs).unwrap();
+    assert!(a.ct_eq(&b).into_bool());
+    assert!(a.ct_eq(&a).into_bool());
+    assert!(!a.ct_eq(&c).into_bool());
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    assert_eq!(c.state.deref_counter.load(Ordering::Acquire), 0);
+  }
+
+  #[test]
+  fn constant_time_ordering() {
+    use core::cmp::Ordering::{Equal, Greater, Less};
+    let a: SensitiveData<[u8; 4]> = SensitiveData::new([1, 2, 3, 4]).unwrap();
+    let b: SensitiveData<[u8; 4]> = SensitiveData::new([1, 2, 4, 0]).unwrap();
+    let c: SensitiveData<[u8; 4]> = SensitiveData::new([1, 2, 3, 4]).unwrap();
+    assert_eq!(a.ct_cmp(&b), Less);
+    assert_eq!(b.ct_cmp(&a), Greater);
+    assert_eq!(a.ct_cmp(&c), Equal);
+    assert_eq!(a.ct_cmp(&a), Equal);
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    assert_eq!(b.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a) && is_inaccessible(&b));
+  }
+
+  #[test]
+  fn copied_to_bytes() {
+    let a: SensitiveData<[u8; 16]> = SensitiveData::new([1; 16]).unwrap();
+    let mut too_long = SensitiveBytes::with_len(32).unwrap();
+    assert!(matches!(a.copy_to(&mut too_long), Err(Error::LengthMismatch)));
+    assert!(too_long.borrow().iter().all(|b| *b == 0));
+    let mut dest = SensitiveBytes::with_len(16).unwrap();
+    a.copy_to(&mut dest).unwrap();
+    assert_eq!(*dest.borrow(), [1; 16]);
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a) && is_inaccessible(&dest) && is_inaccessible(&too_long));
+  }
+
+  #[test]
+  fn hashed_through_newtype() {
+    use std::{
+      collections::HashMap,
+      hash::{Hash, Hasher},
+    };
+    struct Key(SensitiveData<[u8; 4]>);
+    impl Hash for Key {
+      fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash_ct(state)
+      }
+    }
+    impl PartialEq for Key {
+      fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into_bool()
+      }
+    }
+    impl Eq for Key {}
+
+    // The borrow state is interior mutable, but the hash doesn't depend on it.
+    #[allow(clippy::mutable_key_type)]
+    let mut map = HashMap::new();
+    map.insert(Key(SensitiveData::new([1, 2, 3, 4]).unwrap()), 1);
+    map.insert(Key(SensitiveData::new([4, 3, 2, 1]).unwrap()), 2);
+    let key = Key(SensitiveData::new([1, 2, 3, 4]).unwrap());
+    assert_eq!(map.get(&key), Some(&1));
+    assert_eq!(map.get(&Key(SensitiveData::new([0; 4]).unwrap())), None);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&key.0));
+  }
+
+  #[cfg(feature = "digest")]
+  #[test]
+  fn digested() {
+    use sha2::Sha256;
+    let a = SensitiveData::new(*b"abc").unwrap();
+    let mut expected = [0u8; 32];
+    // From FIPS 180-2.
+    assert!(ct::decode_hex(b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                           &mut expected));
+    assert_eq!(a.digest::<Sha256>()[..], expected);
+    assert_eq!(SensitiveBytes::from_slice(b"abc").unwrap()
+                                                 .digest::<Sha256>()[..],
+               expected);
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn constant_time_equality_with_slice() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([1; 32]).unwrap();
+    assert!(a.ct_eq_slice(&[1; 32]).into_bool());
+    let mut last_differs = [1; 32];
+    last_differs[31] = 2;
+    assert!(!a.ct_eq_slice(&last_differs).into_bool());
+    assert!(!a.ct_eq_slice(&[1; 31]).into_bool());
+    assert!(!a.ct_eq_slice(&[1; 33]).into_bool());
+    assert!(!a.ct_eq_slice(&[]).into_bool());
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn read_through_raw_pointer() {
+    let a = SensitiveData::new([4u8; 32]).unwrap();
+    {
+      let raw = a.as_ptr_readable();
+      assert_eq!(raw.len(), 32);
+      #[cfg(target_family = "unix")]
+      assert!(!is_inaccessible(&a));
+      let bytes = unsafe { std::slice::from_raw_parts(raw.as_ptr(), raw.len()) };
+      assert_eq!(bytes, &[4; 32]);
+    }
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    let b = SensitiveBytes::from_slice(b"secret").unwrap();
+    assert_eq!(b.as_ptr_readable().len(), 6);
+  }
+
+  #[test]
+  fn projected_field() {
+    struct Credentials {
+      user: [u8; 4],
+      password: [u8; 8],
+    }
+    let a = SensitiveData::new(Credentials { user: *b"user",
+                                             password: *b"password" }).unwrap();
+    {
+      let password = a.project(|credentials| &credentials.password);
+      assert_eq!(&*password, b"password");
+      #[cfg(target_family = "unix")]
+      assert!(!is_inaccessible(&a));
+      assert_eq!(&a.project(|credentials| &credentials.user[..2])[..], b"us");
+    }
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn debug_is_redacted() {
+    let a: SensitiveData<[u8; 4]> = SensitiveData::new([42; 4]).unwrap();
+    let expected = format!("SensitiveData {{ len: {}, accessible: false }}",
+                           page_size());
+    assert_eq!(format!("{:?}", a), expected);
+    let b = a.borrow();
+    assert_eq!(format!("{:?}", b),
+               format!("DerefHolder {{ holder: {} }}", expected));
+    assert_eq!(b[0], 42);
+    assert!(format!("{:?}", b).contains("accessible: true"));
+    let relaxed = SensitiveData::new_relaxed([42u8; 4]).unwrap();
+    assert!(format!("{:?}", relaxed).contains("accessible: true"));
+  }
+
+  /// Runs `f` in a forked child, which exits with the code it returns, and
+  /// returns the wait status of the child.
+  #[cfg(all(target_family = "unix", not(miri)))]
+  pub(crate) fn in_child(f: impl FnOnce() -> i32) -> i32 {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    unsafe {
+      let pid = libc::fork();
+      assert!(pid >= 0);
+      if pid == 0 {
+        libc::_exit(catch_unwind(AssertUnwindSafe(f)).unwrap_or(101));
+      }
+      let mut status = 0;
+      assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+      status
+    }
+  }
+
+  /// Runs `f` in a forked child, and returns whether it crashed.
+  #[cfg(all(target_family = "unix", not(miri)))]
+  pub(crate) fn crashes_in_child(f: impl FnOnce()) -> bool {
+    let status = in_child(|| {
+      f();
+      0
+    });
+    libc::WIFSIGNALED(status) && [libc::SIGSEGV, libc::SIGBUS].contains(&libc::WTERMSIG(status))
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn guard_pages_fault() {
+    let mut a = SensitiveDataBuilder::new().guard_pages(true)
+                                           .build([0u8; 32])
+                                           .unwrap();
+    let len = a.memory_layout.size();
+    a.with_mut(|value| {
+       let ptr = value.as_mut_ptr();
+       assert!(!crashes_in_child(|| unsafe { ptr.write_volatile(1) }));
+       assert!(!crashes_in_child(|| unsafe { ptr.add(len - 1).write_volatile(1) }));
+       assert!(crashes_in_child(|| unsafe { ptr.add(len).write_volatile(1) }));
+       assert!(crashes_in_child(|| unsafe { ptr.sub(1).write_volatile(1) }));
+     });
+  }
+
+  #[cfg(feature = "zeroize")]
+  struct WithHeap {
+    data: Vec<u8>,
+    heap_zeroized: *mut bool,
+  }
+
+  #[cfg(feature = "zeroize")]
+  impl zeroize::Zeroize for WithHeap {
+    fn zeroize(&mut self) {
+      self.data.zeroize();
+    }
+  }
+
+  #[cfg(feature = "zeroize")]
+  impl Drop for WithHeap {
+    fn drop(&mut self) {
+      // `Vec::zeroize` clears the whole capacity, so it's all initialized.
+      let heap = unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.data.capacity()) };
+      unsafe { *self.heap_zeroized = heap.iter().all(|b| *b == 0) };
+    }
+  }
+
+  #[cfg(feature = "zeroize")]
+  #[test]
+  fn zeroizes_heap_of_value() {
+    let mut heap_zeroized = false;
+    let a = SensitiveData::new_zeroizing(WithHeap { data: vec![1; 64],
+                                                    heap_zeroized: &mut heap_zeroized }).unwrap();
+    drop(a);
+    assert!(heap_zeroized);
+    let a = SensitiveData::new(WithHeap { data: vec![1; 64],
+                                          heap_zeroized: &mut heap_zeroized }).unwrap();
+    drop(a);
+    assert!(!heap_zeroized);
+  }
+
+  #[test]
+  fn map_derives_new_secret() {
+    let mut master = [0u8; 32];
+    master.iter_mut()
+          .enumerate()
+          .for_each(|(i, b)| *b = i as u8);
+    let master: SensitiveData<[u8; 32]> = SensitiveData::new(master).unwrap();
+    let subkey: SensitiveData<[u8; 16]> = master.map(|master| {
+                                                  let mut subkey = [0; 16];
+                                                  for i in 0..16 {
+                                                    subkey[i] = master[i] ^ master[i + 16];
+                                                  }
+                                                  subkey
+                                                })
+                                                .unwrap();
+    assert_eq!(*subkey.borrow(), [16; 16]);
+  }
+
+  #[test]
+  fn map_panic_does_not_drop_result() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    let result =
+      catch_unwind(AssertUnwindSafe(|| a.map::<WithDestructor>(|_| panic!("Inside map"))));
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn locking() {
+    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    assert!(a.is_locked());
+    let a: SensitiveData<SomeTestStruct> =
+      SensitiveData::new_unlocked(SomeTestStruct { a: 1 }).unwrap();
+    assert!(!a.is_locked());
+    assert_eq!(a.borrow().a, 1);
+    let a: SensitiveData<SomeTestStruct> =
+      SensitiveData::new_best_effort(SomeTestStruct { a: 1 }).unwrap();
+    assert!(a.is_locked());
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn best_effort_falls_back_when_lock_limited() {
+    for errno in [libc::EPERM, libc::ENOMEM, libc::EAGAIN] {
+      sys::FAIL_LOCK.with(|fail| fail.set(Some(errno)));
+      assert!(matches!(SensitiveData::new(SomeTestStruct { a: 1 }),
+                       Err(Error::LockLimitExceeded(e)) if e.raw_os_error() == Some(errno)));
+      let a = SensitiveData::new_best_effort(SomeTestStruct { a: 1 }).unwrap();
+      assert!(!a.is_locked());
+      assert_eq!(a.borrow().a, 1);
+    }
+    sys::FAIL_LOCK.with(|fail| fail.set(Some(libc::EINVAL)));
+    assert!(matches!(SensitiveData::new(SomeTestStruct { a: 1 }),
+                     Err(Error::IoError(_))));
+    assert!(SensitiveData::new_best_effort(SomeTestStruct { a: 1 }).is_err());
+    sys::FAIL_LOCK.with(|fail| fail.set(None));
+  }
+
+  /// The amount of memory locked by the process, in kilobytes.
+  #[cfg(all(target_os = "linux", not(miri)))]
+  fn locked_kb() -> usize {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    status.lines()
+          .find_map(|line| line.strip_prefix("VmLck:"))
+          .and_then(|locked| locked.trim().strip_suffix("kB"))
+          .map(|locked| locked.trim().parse().unwrap())
+          .unwrap()
+  }
+
+  #[cfg(all(feature = "std", target_os = "linux", not(miri)))]
+  #[test]
+  fn locked_according_to_smaps() {
+    let a = SensitiveData::new([1u8; 32]).unwrap();
+    let b = SensitiveData::new_unlocked([1u8; 32]).unwrap();
+    assert!(a.verify_locked().unwrap());
+    assert!(!b.verify_locked().unwrap());
+    let c = SensitiveBytes::with_len(3 * page_size()).unwrap();
+    assert!(c.verify_locked().unwrap());
+  }
+
+  /// Runs in a child, so that no other test locks memory meanwhile.
+  #[cfg(all(target_os = "linux", not(miri)))]
+  #[test]
+  fn unlocked_when_dropped() {
+    let status = in_child(|| {
+      let before = locked_kb();
+      let limit = libc::rlimit { rlim_cur: 64 << 10,
+                                 rlim_max: 64 << 10 };
+      unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) };
+      for _ in 0..1000 {
+        let a = SensitiveData::new([1u8; 16 << 10]).unwrap();
+        assert!(locked_kb() > before);
+        drop(a);
+      }
+      (locked_kb() != before) as i32
+    });
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+  }
+
+  /// Lowers `RLIMIT_MEMLOCK` to nothing in a child, so that the real limit is
+  /// hit. Skipped if the limit isn't enforced, such as with `CAP_IPC_LOCK`.
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn lock_limit_exceeded() {
+    let status = in_child(|| {
+      let limit = libc::rlimit { rlim_cur: 0,
+                                 rlim_max: 0 };
+      if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) } != 0 {
+        return 2;
+      }
+      match SensitiveData::new([1u8; 32]) {
+        Ok(_) => 2,
+        Err(Error::LockLimitExceeded(_)) => 0,
+        Err(_) => 1,
+      }
+    });
+    assert!(libc::WIFEXITED(status));
+    match libc::WEXITSTATUS(status) {
+      0 => {}
+      2 => println!("RLIMIT_MEMLOCK isn't enforced, skipping"),
+      code => panic!("Locking failed with another error, in child exiting with {}",
+                     code),
+    }
+  }
+
+  /// Fails to lock a lot of memory over and over in a child, to see that none
+  /// of it is kept. Skipped if the limit isn't enforced.
+  #[cfg(all(target_os = "linux", not(miri)))]
+  #[test]
+  fn freed_when_lock_limit_exceeded() {
+    fn virtual_kb() -> usize {
+      let status = std::fs::read_to_string("/proc/self/status").unwrap();
+      status.lines()
+            .find_map(|line| line.strip_prefix("VmSize:"))
+            .and_then(|size| size.trim().strip_suffix("kB"))
+            .map(|size| size.trim().parse().unwrap())
+            .unwrap()
+    }
+    let status = in_child(|| {
+      let limit = libc::rlimit { rlim_cur: 64 << 10,
+                                 rlim_max: 64 << 10 };
+      if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) } != 0 {
+        return 2;
+      }
+      let (locked, size) = (locked_kb(), virtual_kb());
+      for _ in 0..1000 {
+        match SensitiveBytes::with_len(1 << 20) {
+          Err(Error::LockLimitExceeded(_)) => {}
+          Ok(_) => return 2,
+          Err(_) => return 1,
+        }
+      }
+      // A leak would be a gigabyte.
+      (locked_kb() != locked || virtual_kb() > size + (64 << 10)) as i32
+    });
+    assert!(libc::WIFEXITED(status));
+    match libc::WEXITSTATUS(status) {
+      0 => {}
+      2 => println!("RLIMIT_MEMLOCK isn't enforced, skipping"),
+      code => panic!("Child exited with {}", code),
+    }
+  }
+
+  /// More than the default minimum working set, so that it has to be grown.
+  #[cfg(all(target_family = "windows", not(miri)))]
+  #[test]
+  fn larger_than_working_set() {
+    let a = SensitiveBytes::with_len(4 << 20).unwrap();
+    assert!(a.is_locked());
+    let b = SensitiveBytes::with_len(4 << 20).unwrap();
+    assert!(b.is_locked());
+  }
+
+  /// The name of the mapping holding `address` in `/proc/self/maps`, if any.
+  #[cfg(all(feature = "named-regions", target_os = "linux", not(miri)))]
+  fn mapping_name(address: usize) -> Option<String> {
+    let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+    maps.lines().find_map(|line| {
+                  let mut fields = line.split_whitespace();
+                  let (start, end) = fields.next()?.split_once('-')?;
+                  let (start, end) =
+                    (usize::from_str_radix(start, 16).ok()?, usize::from_str_radix(end, 16).ok()?);
+                  (start..end).contains(&address)
+                              .then(|| fields.nth(4).unwrap_or_default().to_owned())
+                })
+  }
+
+  #[cfg(all(feature = "named-regions", target_os = "linux", not(miri)))]
+  #[test]
+  fn named_region() {
+    let a = SensitiveData::new([1u8; 32]).unwrap();
+    let address = a.inner_ptr as usize;
+    if mapping_name(address).as_deref() != Some("[anon:sensitive-data]") {
+      // Whether the kernel can name memory at all.
+      let (ptr, len) = a.protected_range();
+      assert!(a.protector.set_name(ptr, len, Some(REGION_NAME)).is_err(),
+              "The memory wasn't named");
+      println!("The kernel can't name memory, skipping");
+      return;
+    }
+    drop(a);
+    assert_ne!(mapping_name(address).as_deref(),
+               Some("[anon:sensitive-data]"));
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn wiped_on_fork() {
+    let kept = SensitiveData::new([7u8; 32]).unwrap();
+    let wiped = SensitiveData::new_wipe_on_fork([7u8; 32]).unwrap();
+    let in_child = |data: &SensitiveData<[u8; 32]>| {
+      let status = in_child(|| data.with_ref(|value| value[0]) as i32);
+      assert!(libc::WIFEXITED(status));
+      libc::WEXITSTATUS(status)
+    };
+    assert_eq!(in_child(&kept), 7);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    assert_eq!(in_child(&wiped), 0);
+    assert_eq!(*wiped.borrow(), [7; 32]);
+    assert_eq!(*wiped.clone().borrow(), [7; 32]);
+  }
+
+  #[test]
+  fn clone_is_independent() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([1; 32]).unwrap();
+    let mut b = a.clone();
+    assert_ne!(a.inner_ptr, b.inner_ptr);
+    assert!(b.is_locked());
+    b.borrow_mut()[0] = 2;
+    assert_eq!(a.borrow()[0], 1);
+    assert_eq!(b.borrow()[0], 2);
+    #[cfg(target_family = "unix")]
+    {
+      assert!(is_inaccessible(&a));
+      assert!(is_inaccessible(&b));
+    }
+  }
+
+  #[test]
+  fn into_inner_moves_value_out() {
+    let a = SensitiveData::new(vec![1u8; 1000]).unwrap();
+    let b = a.clone();
+    assert_eq!(a.into_inner(), vec![1u8; 1000]);
+    assert_eq!(b.into_inner(), vec![1u8; 1000]);
+  }
+
+  #[test]
+  fn into_inner_does_not_drop() {
+    let mut destructor_executed = false;
+    let a =
+      SensitiveData::new(WithDestructor { destructor_executed: &mut destructor_executed }).unwrap();
+    let value = a.into_inner();
+    assert!(!destructor_executed);
+    drop(value);
+    assert!(destructor_executed);
+  }
+
+  struct SelfReferential {
+    value: u8,
+    value_ptr: *const u8,
+    _marker: PhantomPinned,
+  }
+
+  #[test]
+  fn pinned_self_reference() {
+    let mut a = SensitiveData::new(SelfReferential { value: 5,
+                                                     value_ptr: core::ptr::null(),
+                                                     _marker: PhantomPinned }).unwrap();
     {
-      a.borrow_mut().destructor_executed = ptr as *mut bool;
-      println!("Borrowed pointer {:p}", a.borrow().destructor_executed);
+      let mut pinned = a.as_pinned_mut();
+      let this = unsafe { pinned.as_mut().get_unchecked_mut() };
+      this.value_ptr = &this.value;
+    }
+    let a = Box::new(a);
+    let pinned = a.as_pinned();
+    assert_eq!(pinned.value_ptr, &pinned.value as *const u8);
+    assert_eq!(unsafe { *pinned.value_ptr }, 5);
+  }
+
+  #[test]
+  fn pinned_mutably_again() {
+    let mut a = SensitiveData::new(SelfReferential { value: 5,
+                                                     value_ptr: core::ptr::null(),
+                                                     _marker: PhantomPinned }).unwrap();
+    for value in 6..9 {
+      let mut pinned = a.try_as_pinned_mut().unwrap();
+      let this = unsafe { pinned.as_mut().get_unchecked_mut() };
+      this.value_ptr = &this.value;
+      this.value = value;
+      assert_eq!(unsafe { *this.value_ptr }, value);
+    }
+    a.freeze();
+    assert!(matches!(a.try_as_pinned_mut(), Err(Error::Frozen)));
+    assert_eq!(unsafe { *a.as_pinned().value_ptr }, 8);
+  }
+
+  #[test]
+  fn no_borrows() {
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    assert_eq!(a.borrow().a, 1);
+    a.assert_no_borrows();
+    a.borrow_mut().a = 2;
+    a.assert_no_mut_borrows();
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  #[should_panic(expected = "SensitiveData is still borrowed")]
+  fn leaked_borrow() {
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    core::mem::forget(a.try_borrow().unwrap());
+    a.assert_no_borrows();
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  #[should_panic(expected = "SensitiveData is still mutably borrowed")]
+  fn leaked_mut_borrow() {
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    core::mem::forget(a.borrow_mut());
+    a.assert_no_mut_borrows();
+  }
+
+  #[test]
+  fn relaxed_stays_readable() {
+    let mut a: SensitiveData<SomeTestStruct> =
+      SensitiveData::new_relaxed(SomeTestStruct { a: 1 }).unwrap();
+    assert!(a.is_locked());
+    #[cfg(target_family = "unix")]
+    assert!(!is_inaccessible(&a));
+    assert_eq!(a.borrow().a, 1);
+    a.borrow_mut().a = 2;
+    #[cfg(all(target_os = "linux", not(miri)))]
+    {
+      let flags = vm_flags(a.inner_ptr as usize);
+      assert!(flags.iter().any(|flag| flag == "rd"));
+      assert!(!flags.iter().any(|flag| flag == "wr"));
+    }
+    let b = a.map(|value| value.a).unwrap();
+    #[cfg(target_family = "unix")]
+    assert!(!is_inaccessible(&b));
+    assert_eq!(*b.borrow(), 2);
+  }
+
+  #[cfg(target_pointer_width = "64")]
+  #[test]
+  fn allocation_failure() {
+    assert!(matches!(unsafe { SensitiveData::<[u8; HUGE]>::new_zeroed() },
+                     Err(Error::AllocError(layout)) if layout.size() >= HUGE));
+    assert!(matches!(SensitiveBytes::with_len(HUGE), Err(Error::AllocError(_))));
+    assert!(matches!(SensitiveVec::with_capacity(HUGE), Err(Error::AllocError(_))));
+  }
+
+  #[test]
+  fn replace_in_place() {
+    let mut first = 0;
+    let mut second = 0;
+    let second_ptr: *mut usize = &mut second;
+    let mut a = SensitiveData::new(CountsDrops { drops: &mut first }).unwrap();
+    let inner_ptr = a.inner_ptr;
+    a.replace(CountsDrops { drops: second_ptr }).unwrap();
+    assert_eq!((first, second), (1, 0));
+    assert_eq!(a.inner_ptr, inner_ptr);
+    assert_eq!(a.borrow().drops, second_ptr);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    drop(a);
+    assert_eq!((first, second), (1, 1));
+  }
+
+  #[test]
+  fn taken_leaving_default() {
+    let mut a = SensitiveData::new([7u8; 32]).unwrap();
+    let inner_ptr = a.inner_ptr;
+    assert_eq!(a.take().unwrap(), [7; 32]);
+    assert_eq!(a.inner_ptr, inner_ptr);
+    assert_eq!(*a.borrow(), [0; 32]);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    a.replace([8; 32]).unwrap();
+    assert_eq!(a.take().unwrap(), [8; 32]);
+    let mut b = SensitiveData::new(String::from("secret")).unwrap();
+    assert_eq!(b.take().unwrap(), "secret");
+    assert!(b.borrow().is_empty());
+    b.freeze();
+    assert!(matches!(b.take(), Err(Error::Frozen)));
+  }
+
+  #[test]
+  fn reset_in_loop() {
+    let mut a = SensitiveData::new([0u8; 32]).unwrap();
+    let inner_ptr = a.inner_ptr;
+    for i in 1..=10 {
+      a.reset_with([i; 32]).unwrap();
+      assert_eq!(*a.borrow(), [i; 32]);
+    }
+    assert_eq!(a.inner_ptr, inner_ptr);
+    assert!(a.is_locked());
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn replace_when_drop_panics() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    struct PanicsOnDrop;
+    impl Drop for PanicsOnDrop {
+      fn drop(&mut self) {
+        panic!("Dropping PanicsOnDrop");
+      }
     }
-    assert_eq!(*ptr, false);
+    let mut drops = 0;
+    let mut a: SensitiveData<Result<PanicsOnDrop, CountsDrops>> =
+      SensitiveData::new(Ok(PanicsOnDrop)).unwrap();
+    let replaced =
+      catch_unwind(AssertUnwindSafe(|| a.replace(Err(CountsDrops { drops: &mut drops }))));
+    assert!(replaced.is_err());
+    assert!(a.borrow().is_err());
+    drop(a);
+    assert_eq!(drops, 1);
+  }
+
+  // The aliasing is undefined behavior, which Miri rightly refuses.
+  #[cfg(all(debug_assertions, not(miri)))]
+  #[test]
+  #[should_panic(expected = "SensitiveData is borrowed while mutably borrowed")]
+  fn borrow_while_mutably_borrowed() {
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 1 }).unwrap();
+    let aliased = &a as *const SensitiveData<SomeTestStruct>;
+    let mut writer = a.borrow_mut();
+    writer.a = 2;
+    let _ = unsafe { &*aliased }.try_borrow();
+  }
+
+  #[test]
+  fn frozen() {
+    let mut a: SensitiveData<[u8; 4]> = SensitiveData::new([1; 4]).unwrap();
+    a.borrow_mut()[0] = 2;
+    assert!(!a.is_frozen());
+    a.freeze();
+    assert!(a.is_frozen());
+    assert!(matches!(a.try_borrow_mut(), Err(Error::Frozen)));
+    assert!(matches!(a.replace([3; 4]), Err(Error::Frozen)));
+    assert_eq!(*a.borrow(), [2, 1, 1, 1]);
+    assert!(a.clone().is_frozen());
+    // Dropping it early still clears it.
+    a.zeroize();
+    assert!(a.is_consumed());
+  }
+
+  #[test]
+  fn zeroized_early() {
+    let mut drops = 0;
+    let mut a = SensitiveData::new((CountsDrops { drops: &mut drops }, [1u8; 32])).unwrap();
+    let address = a.inner_ptr as usize;
+    assert!(!a.is_consumed());
+    a.zeroize();
+    assert!(a.is_consumed());
+    assert!(!a.verify());
+    assert_eq!(drops, 1);
+    a.make_readable().unwrap();
+    assert!(a.inner_bytes().iter().all(|b| *b == 0));
+    a.make_inaccessible().unwrap();
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    assert!(matches!(a.try_borrow(), Err(Error::Consumed)));
+    assert!(matches!(a.try_borrow_mut(), Err(Error::Consumed)));
+    assert!(matches!(a.replace((CountsDrops { drops: &mut drops }, [2; 32])),
+                     Err(Error::Consumed)));
+    // The rejected replacement is dropped.
+    assert_eq!(drops, 2);
+    a.zeroize();
     drop(a);
-    assert_eq!(*ptr, true);
+    assert_eq!(drops, 2);
+    assert!(freed_zeroized(address));
+  }
+
+  #[test]
+  fn zeroized_early_then_moved_out() {
+    let mut a = SensitiveData::new([1u8; 32]).unwrap();
+    a.zeroize();
+    let (a, e) = a.try_into_inner().unwrap_err();
+    assert!(matches!(e, Error::Consumed));
+    assert!(a.is_consumed());
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| a.borrow()[0])).is_err());
+  }
+
+  #[test]
+  fn drop_hook() {
+    use std::sync::{Arc, Mutex};
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let mut a: SensitiveData<[u8; 40]> = SensitiveData::new([1; 40]).unwrap();
+    let address = a.inner_ptr as usize;
+    let recorded = dropped.clone();
+    a.on_drop(move |size| {
+       recorded.lock()
+               .unwrap()
+               .push((size, freed_zeroized(address)))
+     });
+    let b = a.clone();
+    drop(a);
+    assert_eq!(*dropped.lock().unwrap(), [(40, true)]);
+    drop(b);
+    let mut c = SensitiveBytes::from_slice(&[1; 7]).unwrap();
+    let recorded = dropped.clone();
+    c.on_drop(move |size| recorded.lock().unwrap().push((size, true)));
+    drop(c);
+    assert_eq!(*dropped.lock().unwrap(), [(40, true), (7, true)]);
+    let mut d = SensitiveData::new(1u32).unwrap();
+    let recorded = dropped.clone();
+    d.on_drop(move |size| recorded.lock().unwrap().push((size, true)));
+    assert_eq!(d.into_inner(), 1);
+    assert_eq!(dropped.lock().unwrap().len(), 2);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+  }
+
+  #[test]
+  fn mutably_borrowed_through_arc() {
+    use std::sync::{Arc, Mutex};
+    let a = Arc::new(SensitiveData::new(0u64).unwrap());
+    let writers = Arc::new(Mutex::new(()));
+    std::thread::scope(|s| {
+      for _ in 0..8 {
+        s.spawn(|| {
+           for _ in 0..100 {
+             let _writer = writers.lock().unwrap();
+             // Every borrow happens under the lock.
+             *unsafe { a.borrow_mut_shared() } += 1;
+           }
+         });
+      }
+    });
+    let _writer = writers.lock().unwrap();
+    assert_eq!(*a.borrow(), 800);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[test]
+  fn integrity() {
+    let mut a = SensitiveData::new_with_integrity([1u8; 32]).unwrap();
+    assert_eq!(a.locked_size(), page_size());
+    assert!(a.verify());
+    a.borrow_mut()[0] = 2;
+    a.replace([3; 32]).unwrap();
+    assert!(a.verify());
+    assert!(a.clone().verify());
+    assert_eq!(a.borrow()[0], 3);
+    // Corrupt the value behind the back of the holder.
+    a.make_writable().unwrap();
+    unsafe { (*a.inner_ptr).value[31] ^= 1 };
+    a.make_idle().unwrap();
+    assert!(!a.verify());
+    assert!(matches!(a.try_borrow(), Err(Error::Corrupted)));
+    assert!(matches!(a.try_borrow_mut(), Err(Error::Corrupted)));
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    // And the tag.
+    let mut b = SensitiveData::new_with_integrity([1u8; 32]).unwrap();
+    b.make_writable().unwrap();
+    unsafe { b.tag_ptr().write(0) };
+    b.make_idle().unwrap();
+    assert!(!b.verify());
+    assert!(SensitiveData::new([1u8; 32]).unwrap().verify());
+  }
+
+  #[test]
+  #[should_panic(expected = "SensitiveData was corrupted")]
+  fn corrupted_borrow() {
+    let mut a = SensitiveData::new_with_integrity([1u8; 32]).unwrap();
+    a.make_writable().unwrap();
+    unsafe { (*a.inner_ptr).value[0] = 2 };
+    a.make_idle().unwrap();
+    let _ = a.borrow()[0];
+  }
+
+  #[test]
+  fn modified_in_place() {
+    let mut a = SensitiveData::new(vec![1u8; 64]).unwrap();
+    a.borrow_mut().modify(|value| value.push(2));
+    a.borrow_mut()
+     .replace_with(|value| value.into_iter().map(|b| b ^ 0xff).collect());
+    assert_eq!(a.borrow().len(), 65);
+    assert!(a.borrow()[..64].iter().all(|b| *b == 0xfe));
+    assert_eq!(a.borrow()[64], 0xfd);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+    let mut b = SensitiveData::new_with_integrity(vec![1u8; 64]).unwrap();
+    b.borrow_mut().replace_with(|mut value| {
+                    value[0] = 2;
+                    value
+                  });
+    assert!(b.verify());
+  }
+
+  #[cfg(all(target_family = "unix", not(miri)))]
+  #[test]
+  fn replace_with_aborts_on_panic() {
+    let status = in_child(|| {
+      let mut a = SensitiveData::new(vec![1u8; 64]).unwrap();
+      a.borrow_mut()
+       .replace_with(|_| panic!("Inside replace_with"));
+      0
+    });
+    assert!(libc::WIFSIGNALED(status));
+    assert_eq!(libc::WTERMSIG(status), libc::SIGABRT);
+  }
+
+  #[test]
+  fn moved_to_thread() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([7; 32]).unwrap();
+    let handle = std::thread::spawn(move || a.borrow()[31]);
+    assert_eq!(handle.join().unwrap(), 7);
   }
   #[test]
   fn multiple_readers() {
@@ -358,6 +3270,101 @@ mod tests {
     let _c = a.borrow();
   }
   #[test]
+  fn concurrent_readers() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([3; 32]).unwrap();
+    std::thread::scope(|s| {
+      for _ in 0..16 {
+        s.spawn(|| {
+           for _ in 0..1000 {
+             assert_eq!(a.borrow()[0], 3);
+           }
+         });
+      }
+    });
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+  }
+  #[test]
+  fn interleaved_borrows() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([3; 32]).unwrap();
+    let mut holders = Vec::new();
+    // A fixed pseudo random walk over how many holders are alive.
+    let mut state = 1u32;
+    for _ in 0..1000 {
+      state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      if state >> 31 == 0 && !holders.is_empty() {
+        holders.swap_remove((state as usize >> 8) % holders.len());
+      } else {
+        holders.push(a.try_borrow().unwrap());
+      }
+      assert_eq!(a.state.deref_counter.load(Ordering::Acquire), holders.len());
+      #[cfg(target_family = "unix")]
+      assert_eq!(is_inaccessible(&a), holders.is_empty());
+    }
+    drop(holders);
+    std::thread::scope(|s| {
+      for _ in 0..8 {
+        s.spawn(|| {
+           for _ in 0..100 {
+             let (b, c) = (a.borrow(), a.try_borrow().unwrap());
+             let d = a.borrow();
+             drop(c);
+             assert_eq!(b[0] + d[31], 6);
+           }
+         });
+      }
+    });
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&a));
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  #[should_panic(expected = "released more often than borrowed")]
+  fn released_without_borrow() {
+    let a = SensitiveData::new([3u8; 32]).unwrap();
+    let _ = a.release_read();
+  }
+
+  #[test]
+  #[should_panic(expected = "Too many borrows")]
+  fn too_many_borrows() {
+    let a = SensitiveData::new([3u8; 32]).unwrap();
+    a.state
+     .deref_counter
+     .store(TRANSITIONING - 1, Ordering::Release);
+    let _ = a.try_borrow();
+  }
+
+  #[test]
+  fn concurrent_deref_of_shared_holder() {
+    let a: SensitiveData<[u8; 32]> = SensitiveData::new([3; 32]).unwrap();
+    for _ in 0..100 {
+      let b = a.borrow();
+      std::thread::scope(|s| {
+        for _ in 0..8 {
+          s.spawn(|| assert_eq!(b[0], 3));
+        }
+      });
+    }
+  }
+  #[test]
+  fn try_borrow_changes_permissions_immediately() {
+    let a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 5 }).unwrap();
+    {
+      let b = a.try_borrow().unwrap();
+      assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 1);
+      assert_eq!(b.a, 5);
+    }
+    assert_eq!(a.state.deref_counter.load(Ordering::Acquire), 0);
+  }
+  #[test]
+  fn try_borrow_mut_writes() {
+    let mut a: SensitiveData<SomeTestStruct> = SensitiveData::new(SomeTestStruct { a: 5 }).unwrap();
+    a.try_borrow_mut().unwrap().a = 6;
+    assert_eq!(a.try_borrow().unwrap().a, 6);
+  }
+  #[test]
   fn reader_then_writer_then_reader() {
     let mut a: SensitiveData<SomeTestStruct> = unsafe { SensitiveData::new_zeroed().unwrap() };
     {
@@ -369,3 +3376,80 @@ mod tests {
     let _c = a.borrow();
   }
 }
+
+/// Run with `RUSTFLAGS="--cfg loom" cargo test --release loom`. Only these
+/// tests run under loom, since its atomics can't be used outside of a model.
+#[cfg(all(test, loom))]
+mod loom_tests {
+  use loom::{cell::UnsafeCell, sync::Arc, thread};
+
+  use super::*;
+
+  /// Tracks the protection in a cell, so that loom reports a data race if a
+  /// reader could look at it without happening after the change.
+  struct Tracking {
+    protection: UnsafeCell<Protection>,
+  }
+
+  // Only ever accessed as loom sees fit.
+  unsafe impl Sync for Tracking {}
+
+  impl Tracking {
+    fn set(&self, protection: Protection) -> Result<(), err::IoError> {
+      self.protection
+          .with_mut(|current| unsafe { *current = protection });
+      Ok(())
+    }
+
+    fn assert_readable(&self) {
+      self.protection
+          .with(|current| assert!(unsafe { *current } != Protection::Inaccessible));
+    }
+  }
+
+  impl MemoryProtector for Tracking {
+    fn page_size(&self) -> usize {
+      64
+    }
+
+    fn lock(&self, _ptr: *mut u8, _len: usize) -> Result<(), err::IoError> {
+      Ok(())
+    }
+
+    fn make_inaccessible(&self, _ptr: *mut u8, _len: usize) -> Result<(), err::IoError> {
+      self.set(Protection::Inaccessible)
+    }
+
+    fn make_readable(&self, _ptr: *mut u8, _len: usize) -> Result<(), err::IoError> {
+      self.set(Protection::Readable)
+    }
+
+    fn make_writable(&self, _ptr: *mut u8, _len: usize) -> Result<(), err::IoError> {
+      self.set(Protection::Writable)
+    }
+  }
+
+  #[test]
+  fn loom_concurrent_borrows() {
+    loom::model(|| {
+      let tracking: &'static Tracking =
+        Box::leak(Box::new(Tracking { protection: UnsafeCell::new(Protection::Writable) }));
+      let data = Arc::new(SensitiveData::with_protector([1u8; 4], tracking).unwrap());
+      let readers: Vec<_> = (0..2).map(|_| {
+                                    let data = data.clone();
+                                    thread::spawn(move || {
+                                      let value = data.borrow();
+                                      assert_eq!(value[0], 1);
+                                      tracking.assert_readable();
+                                    })
+                                  })
+                                  .collect();
+      for reader in readers {
+        reader.join().unwrap();
+      }
+      assert_eq!(data.state.deref_counter.load(Ordering::Acquire), 0);
+      tracking.protection
+              .with(|current| assert!(unsafe { *current } == Protection::Inaccessible));
+    });
+  }
+}
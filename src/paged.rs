@@ -0,0 +1,296 @@
+//! Byte buffers too large to lock as a whole. Only a window of them is kept
+//! decrypted in locked memory, while the rest is encrypted on the regular heap
+//! with a key that is itself locked.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::{err, zeroize, zeroize_stack, Error, SensitiveBytes, SensitiveData};
+
+/// Bytes of which only `window_pages` pages at a time are decrypted into locked
+/// memory, with the rest encrypted under an ephemeral ChaCha20 key. Ranges are
+/// paged in as they're accessed, and those that were written are encrypted
+/// again, under a fresh nonce, once they're paged out. Created with
+/// `SensitiveData::new_paged`.
+///
+/// The encryption only keeps the bytes out of reach of anything that can see
+/// the unlocked memory, such as swap or a core dump. It doesn't detect
+/// tampering.
+pub struct SensitivePagedBytes {
+  key: SensitiveData<[u8; 32]>,
+  ciphertext: Vec<u8>,
+  /// The nonce each page was last encrypted with.
+  nonces: Vec<u64>,
+  next_nonce: u64,
+  window: SensitiveBytes,
+  /// The pages currently decrypted into the window.
+  resident: Range<usize>,
+  dirty: bool,
+  page_size: usize,
+}
+
+impl SensitiveData<[u8]> {
+  /// Copies `bytes` into a `SensitivePagedBytes`, which locks only
+  /// `window_pages` pages of it at a time. The bytes are encrypted straight
+  /// from `bytes`, which is left untouched, so it's up to the caller to clear
+  /// it. Fails with `Error::WindowTooSmall` if `window_pages` is 0.
+  pub fn new_paged(bytes: &[u8], window_pages: usize) -> Result<SensitivePagedBytes, Error> {
+    if window_pages == 0 {
+      return Err(Error::WindowTooSmall);
+    }
+    let mut key = SensitiveData::new([0u8; 32])?;
+    getrandom::fill(&mut *key.try_borrow_mut()?).map_err(random_error)?;
+    let page_size = key.protector.page_size();
+    // Too many pages saturates to a length no layout can hold.
+    let window = SensitiveData::with_len(window_pages.saturating_mul(page_size))?;
+    let mut paged = SensitivePagedBytes { key,
+                                          ciphertext: alloc::vec![0; bytes.len()],
+                                          nonces: alloc::vec![0; bytes.len().div_ceil(page_size)],
+                                          next_nonce: 0,
+                                          window,
+                                          resident: 0..0,
+                                          dirty: false,
+                                          page_size };
+    {
+      let key = paged.key.try_borrow()?;
+      for (page, plaintext) in bytes.chunks(page_size).enumerate() {
+        paged.nonces[page] = paged.next_nonce;
+        chacha20(&key,
+                 paged.next_nonce,
+                 plaintext,
+                 &mut paged.ciphertext[page * page_size..][..plaintext.len()]);
+        paged.next_nonce += 1;
+      }
+    }
+    zeroize_stack();
+    Ok(paged)
+  }
+}
+
+impl SensitivePagedBytes {
+  pub fn len(&self) -> usize {
+    self.ciphertext.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ciphertext.is_empty()
+  }
+
+  /// How many pages are decrypted at a time.
+  pub fn window_pages(&self) -> usize {
+    self.window.len() / self.page_size
+  }
+
+  /// Calls `f` with the bytes in `range`, paging them in first if needed.
+  /// Fails with `Error::WindowTooSmall` if they span more pages than the
+  /// window holds. Panics if `range` is out of bounds, like slicing does.
+  pub fn with_range<R>(&mut self,
+                       range: Range<usize>,
+                       f: impl FnOnce(&[u8]) -> R)
+                       -> Result<R, Error> {
+    let range = self.page_in(range)?;
+    Ok(f(&self.window.try_borrow()?[range]))
+  }
+
+  /// Like `with_range`, but lets `f` change the bytes. They're encrypted again
+  /// once they're paged out.
+  pub fn with_range_mut<R>(&mut self,
+                           range: Range<usize>,
+                           f: impl FnOnce(&mut [u8]) -> R)
+                           -> Result<R, Error> {
+    let range = self.page_in(range)?;
+    self.dirty = true;
+    Ok(f(&mut self.window.try_borrow_mut()?[range]))
+  }
+
+  /// Makes sure that `range` is in the window, and returns where.
+  fn page_in(&mut self, range: Range<usize>) -> Result<Range<usize>, Error> {
+    assert!(range.start <= range.end && range.end <= self.len(),
+            "Range {:?} out of bounds for {} bytes",
+            range,
+            self.len());
+    let first = range.start / self.page_size;
+    let last = range.end.saturating_sub(1).max(range.start) / self.page_size;
+    if last - first >= self.window_pages() {
+      return Err(Error::WindowTooSmall);
+    }
+    if first < self.resident.start || last >= self.resident.end {
+      self.page_out()?;
+      let pages = first..(first + self.window_pages()).min(self.nonces.len());
+      {
+        let key = self.key.try_borrow()?;
+        let mut window = self.window.try_borrow_mut()?;
+        for page in pages.clone() {
+          let bytes = page_bytes(page, self.page_size, self.ciphertext.len());
+          let offset = (page - first) * self.page_size;
+          chacha20(&key,
+                   self.nonces[page],
+                   &self.ciphertext[bytes.clone()],
+                   &mut window[offset..][..bytes.len()]);
+        }
+      }
+      zeroize_stack();
+      self.resident = pages;
+    }
+    let offset = self.resident.start * self.page_size;
+    Ok(range.start - offset..range.end - offset)
+  }
+
+  /// Encrypts the window back into the ciphertext if it was written to, and
+  /// clears it.
+  fn page_out(&mut self) -> Result<(), Error> {
+    {
+      let key = self.key.try_borrow()?;
+      let mut window = self.window.try_borrow_mut()?;
+      if self.dirty {
+        for page in self.resident.clone() {
+          let bytes = page_bytes(page, self.page_size, self.ciphertext.len());
+          let offset = (page - self.resident.start) * self.page_size;
+          self.nonces[page] = self.next_nonce;
+          chacha20(&key,
+                   self.next_nonce,
+                   &window[offset..][..bytes.len()],
+                   &mut self.ciphertext[bytes]);
+          self.next_nonce += 1;
+        }
+      }
+      zeroize(&mut window);
+    }
+    zeroize_stack();
+    self.resident = 0..0;
+    self.dirty = false;
+    Ok(())
+  }
+}
+
+/// The bytes of `page` in a buffer of `len` bytes.
+fn page_bytes(page: usize, page_size: usize, len: usize) -> Range<usize> {
+  let start = page * page_size;
+  start..(start + page_size).min(len)
+}
+
+fn random_error(e: getrandom::Error) -> Error {
+  match e.raw_os_error() {
+    Some(code) => Error::IoError(err::IoError::from_raw_os_error(code)),
+    None => Error::IoError(err::unsupported()),
+  }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+  state[a] = state[a].wrapping_add(state[b]);
+  state[d] = (state[d] ^ state[a]).rotate_left(16);
+  state[c] = state[c].wrapping_add(state[d]);
+  state[b] = (state[b] ^ state[c]).rotate_left(12);
+  state[a] = state[a].wrapping_add(state[b]);
+  state[d] = (state[d] ^ state[a]).rotate_left(8);
+  state[c] = state[c].wrapping_add(state[d]);
+  state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Writes `input` XOR the ChaCha20 (RFC 8439) keystream for `key` and `nonce`
+/// to `output`, starting from block 0.
+fn chacha20(key: &[u8; 32], nonce: u64, input: &[u8], output: &mut [u8]) {
+  debug_assert_eq!(input.len(), output.len());
+  let mut initial = [0u32; 16];
+  initial[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+  for (word, bytes) in initial[4..12].iter_mut().zip(key.chunks_exact(4)) {
+    *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+  }
+  initial[13] = nonce as u32;
+  initial[14] = (nonce >> 32) as u32;
+  let mut keystream = [0u8; 64];
+  for (block, (input, output)) in input.chunks(64).zip(output.chunks_mut(64)).enumerate() {
+    initial[12] = block as u32;
+    let mut state = initial;
+    for _ in 0..10 {
+      quarter_round(&mut state, 0, 4, 8, 12);
+      quarter_round(&mut state, 1, 5, 9, 13);
+      quarter_round(&mut state, 2, 6, 10, 14);
+      quarter_round(&mut state, 3, 7, 11, 15);
+      quarter_round(&mut state, 0, 5, 10, 15);
+      quarter_round(&mut state, 1, 6, 11, 12);
+      quarter_round(&mut state, 2, 7, 8, 13);
+      quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (bytes, (word, initial)) in keystream.chunks_exact_mut(4)
+                                             .zip(state.iter().zip(&initial))
+    {
+      bytes.copy_from_slice(&word.wrapping_add(*initial).to_le_bytes());
+    }
+    for ((output, input), keystream) in output.iter_mut().zip(input).zip(&keystream) {
+      *output = input ^ keystream;
+    }
+  }
+  zeroize(&mut keystream);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tests::page_size;
+
+  #[test]
+  fn keystream() {
+    // From Python's `cryptography`, which implements RFC 8439.
+    let key = core::array::from_fn(|i| i as u8);
+    let mut out = [0u8; 80];
+    chacha20(&key, 0x0123_4567_89ab_cdef, &[0; 80], &mut out);
+    let expected = "470097599a8e2658b62d849b6925c9a223c3f3fcf8af518fa2e792929b6cb819a70fdb02207c55\
+                    83ea1b42ccafb61301c8138cb51613da3e584c3c13ed712a2fa2a2636112ac7de53a1e27d889f4\
+                    dbcd";
+    let mut expected_bytes = [0u8; 80];
+    assert!(crate::ct::decode_hex(expected.as_bytes(), &mut expected_bytes));
+    assert_eq!(out, expected_bytes);
+  }
+
+  fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+  }
+
+  #[test]
+  fn larger_than_window() {
+    let bytes = pattern(5 * page_size() + 17);
+    let mut paged = SensitiveData::new_paged(&bytes, 2).unwrap();
+    assert_eq!(paged.len(), bytes.len());
+    assert_eq!(paged.window.len(), 2 * page_size());
+    assert_ne!(paged.ciphertext, bytes);
+    for page in (0..6).chain([3, 0, 5]) {
+      let range = page * page_size()..((page + 1) * page_size()).min(bytes.len());
+      assert_eq!(paged.with_range(range.clone(), <[u8]>::to_vec).unwrap(),
+                 &bytes[range]);
+    }
+    let across = page_size() - 3..page_size() + 3;
+    assert_eq!(paged.with_range(across.clone(), <[u8]>::to_vec).unwrap(),
+               &bytes[across]);
+    assert!(matches!(paged.with_range(0..2 * page_size() + 1, |_| ()),
+                     Err(Error::WindowTooSmall)));
+  }
+
+  #[test]
+  fn written_pages_encrypted_again() {
+    let bytes = pattern(4 * page_size());
+    let mut paged = SensitiveData::new_paged(&bytes, 1).unwrap();
+    let before = paged.ciphertext.clone();
+    paged.with_range_mut(page_size()..page_size() + 4, |b| b.copy_from_slice(b"abcd"))
+         .unwrap();
+    paged.with_range(3 * page_size()..3 * page_size() + 1, |_| ())
+         .unwrap();
+    assert_eq!(paged.ciphertext[..page_size()], before[..page_size()]);
+    assert_ne!(paged.ciphertext[page_size()..2 * page_size()],
+               before[page_size()..2 * page_size()]);
+    assert!(!paged.ciphertext.windows(4).any(|window| window == b"abcd"));
+    let mut expected = bytes;
+    expected[page_size()..page_size() + 4].copy_from_slice(b"abcd");
+    for page in 0..4 {
+      let range = page * page_size()..(page + 1) * page_size();
+      assert_eq!(paged.with_range(range.clone(), <[u8]>::to_vec).unwrap(),
+                 &expected[range]);
+    }
+  }
+
+  #[test]
+  fn no_window() {
+    assert!(matches!(SensitiveData::new_paged(&[1, 2, 3], 0),
+                     Err(Error::WindowTooSmall)));
+  }
+}
@@ -0,0 +1,236 @@
+//! Hardening of the whole process. Unlike everything else in this crate it
+//! affects more than the memory of a `SensitiveData`, so nothing here is done
+//! unless asked for, either directly or through `auto_harden`.
+
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+use core::sync::atomic::AtomicU64;
+#[cfg(feature = "std")]
+use std::sync::{
+  atomic::{AtomicBool, AtomicI32, Ordering},
+  Once,
+};
+
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+use crate::sys;
+use crate::{err::Error, SensitiveData};
+
+/// Set by `auto_harden`.
+#[cfg(feature = "std")]
+static AUTO_HARDEN: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "std")]
+static HARDENED: Once = Once::new();
+/// The raw OS error the automatic hardening failed with, or 0 if it didn't
+/// fail. The error itself can't be cloned for every allocation that follows.
+#[cfg(feature = "std")]
+static HARDEN_ERROR: AtomicI32 = AtomicI32::new(0);
+/// The signals blocked while a value is accessible, with bit `n - 1` set for
+/// signal `n`. Set by `block_signals_during_access`.
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+static BLOCKED_SIGNALS: AtomicU64 = AtomicU64::new(0);
+/// How many times the process was hardened automatically.
+#[cfg(all(test, feature = "std"))]
+static AUTO_HARDENINGS: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+impl SensitiveData<()> {
+  /// Marks the process as not dumpable, with `prctl(PR_SET_DUMPABLE, 0)` on
+  /// Linux. This keeps processes without `CAP_SYS_PTRACE` from attaching to it
+  /// or reading its memory through `/proc/<pid>/mem`, and disables core dumps
+  /// entirely. Locked memory stays readable to a debugger otherwise. On every
+  /// unix, the soft limit on core dumps is also lowered to nothing.
+  ///
+  /// This applies to the whole process, until it calls `execve`, and can't be
+  /// scoped to a single secret. It does nothing on platforms without `prctl`
+  /// or `setrlimit`.
+  pub fn harden_process() -> Result<(), Error> {
+    #[cfg(any(target_family = "unix", target_family = "windows"))]
+    sys::harden_process()?;
+    Ok(())
+  }
+
+  /// Has the process hardened like `harden_process` right before the next
+  /// `SensitiveData` is allocated, unless that already happened. It's done
+  /// exactly once per process, and if it fails, that allocation and every one
+  /// after it fail with the error, since the process stays unhardened. For applications that want hardening without calling
+  /// `harden_process` from their own initialization.
+  #[cfg(feature = "std")]
+  pub fn auto_harden() {
+    AUTO_HARDEN.store(true, Ordering::Release);
+  }
+
+  /// Blocks `signals` in the calling thread while `with_ref` and `with_mut`
+  /// run, so that a signal handler can't see the value while it's readable, or
+  /// interrupt the protection changes around it. The signals are delivered
+  /// once the memory is inaccessible again. Holders from `borrow` and
+  /// `borrow_mut` aren't covered, since they may be kept around for anything.
+  ///
+  /// This applies to every `SensitiveData` in the process, and an empty list
+  /// turns it off again. Fails with `EINVAL` for anything that isn't a signal.
+  /// `SIGKILL` and `SIGSTOP` can't be blocked, and a fault such as `SIGSEGV`
+  /// kills the process outright while it's blocked.
+  #[cfg(all(feature = "block-signals", target_family = "unix"))]
+  pub fn block_signals_during_access(signals: &[i32]) -> Result<(), Error> {
+    BLOCKED_SIGNALS.store(sys::signal_mask(signals)?, Ordering::Release);
+    Ok(())
+  }
+
+  /// The soft and hard limits on how many bytes of memory the process may
+  /// lock, from `RLIMIT_MEMLOCK`, with `u64::MAX` for unlimited. Every locked
+  /// `SensitiveData` counts against the soft limit. Fails with an unsupported
+  /// error where there is no such limit, such as on Windows.
+  pub fn current_memlock_limit() -> Result<(u64, u64), Error> {
+    #[cfg(any(target_family = "unix", target_family = "windows"))]
+    return Ok(sys::memlock_limit()?);
+    #[cfg(not(any(target_family = "unix", target_family = "windows")))]
+    return Err(Error::IoError(crate::err::unsupported()));
+  }
+
+  /// Raises the soft limit on locked memory to `soft` bytes, such as at
+  /// startup by an application that needs more locked pages than the default
+  /// allows. It's left alone if it's already that high. Anything above the
+  /// hard limit is refused by the OS. Fails like `current_memlock_limit` where
+  /// there is no such limit.
+  pub fn try_raise_memlock_limit(soft: u64) -> Result<(), Error> {
+    #[cfg(any(target_family = "unix", target_family = "windows"))]
+    return Ok(sys::raise_memlock_limit(soft)?);
+    #[cfg(not(any(target_family = "unix", target_family = "windows")))]
+    {
+      let _ = soft;
+      Err(Error::IoError(crate::err::unsupported()))
+    }
+  }
+}
+
+/// Blocks the signals from `block_signals_during_access` until the returned
+/// value is dropped.
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+#[inline(always)]
+pub(crate) fn block_signals() -> Option<sys::BlockedSignals> {
+  sys::block_signals(BLOCKED_SIGNALS.load(Ordering::Acquire))
+}
+
+/// Hardens the process, the first time it's called after `auto_harden`, and
+/// fails every time after that if the hardening did.
+#[cfg(feature = "std")]
+#[inline(always)]
+pub(crate) fn harden_if_enabled() -> Result<(), Error> {
+  if !AUTO_HARDEN.load(Ordering::Acquire) {
+    return Ok(());
+  }
+  HARDENED.call_once(|| {
+            #[cfg(test)]
+            AUTO_HARDENINGS.fetch_add(1, Ordering::Relaxed);
+            if let Err(Error::IoError(e)) = SensitiveData::harden_process() {
+              // Every error is from the OS, so there's always a code.
+              HARDEN_ERROR.store(e.raw_os_error().unwrap_or(-1), Ordering::Release);
+            }
+          });
+  match HARDEN_ERROR.load(Ordering::Acquire) {
+    0 => Ok(()),
+    code => Err(crate::err::IoError::from_raw_os_error(code).into()),
+  }
+}
+
+#[cfg(all(test, not(miri), any(target_os = "linux", target_os = "android")))]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn not_dumpable() {
+    // Hardening is irreversible, so keep it away from the other tests.
+    let status = crate::tests::in_child(|| {
+      SensitiveData::harden_process().unwrap();
+      unsafe { libc::prctl(libc::PR_GET_DUMPABLE) }
+    });
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+    assert_eq!(unsafe { libc::prctl(libc::PR_GET_DUMPABLE) }, 1);
+  }
+
+  #[cfg(feature = "block-signals")]
+  #[test]
+  fn signal_deferred_during_access() {
+    static DELIVERED: AtomicBool = AtomicBool::new(false);
+    extern "C" fn handler(_: libc::c_int) {
+      DELIVERED.store(true, Ordering::SeqCst);
+    }
+    // Both the handler and the blocked signals are process wide.
+    let status = crate::tests::in_child(|| {
+      unsafe { libc::signal(libc::SIGUSR1, handler as *const () as libc::sighandler_t) };
+      assert!(SensitiveData::block_signals_during_access(&[0]).is_err());
+      SensitiveData::block_signals_during_access(&[libc::SIGUSR1]).unwrap();
+      let mut a = SensitiveData::new([1u8; 32]).unwrap();
+      let raise = || {
+        unsafe { libc::pthread_kill(libc::pthread_self(), libc::SIGUSR1) };
+        DELIVERED.load(Ordering::SeqCst)
+      };
+      assert!(!a.with_ref(|_| raise()));
+      assert!(DELIVERED.swap(false, Ordering::SeqCst));
+      assert!(!a.with_mut(|_| raise()));
+      assert!(DELIVERED.swap(false, Ordering::SeqCst));
+      SensitiveData::block_signals_during_access(&[]).unwrap();
+      assert!(a.with_ref(|_| raise()));
+      0
+    });
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+  }
+
+  #[test]
+  fn memlock_limit() {
+    let (soft, hard) = SensitiveData::current_memlock_limit().unwrap();
+    assert!(soft <= hard);
+    // Lowering is left alone.
+    SensitiveData::try_raise_memlock_limit(0).unwrap();
+    assert_eq!(SensitiveData::current_memlock_limit().unwrap(),
+               (soft, hard));
+  }
+
+  #[test]
+  fn memlock_limit_raised() {
+    // The limit is process wide, so keep the changes away from the other
+    // tests.
+    let status = crate::tests::in_child(|| {
+      let limit = libc::rlimit { rlim_cur: 64 << 10,
+                                 rlim_max: 1 << 20 };
+      if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) } != 0 {
+        return 2;
+      }
+      assert_eq!(SensitiveData::current_memlock_limit().unwrap(),
+                 (64 << 10, 1 << 20));
+      SensitiveData::try_raise_memlock_limit(512 << 10).unwrap();
+      assert_eq!(SensitiveData::current_memlock_limit().unwrap(),
+                 (512 << 10, 1 << 20));
+      assert!(SensitiveData::try_raise_memlock_limit(2 << 20).is_err());
+      SensitiveData::try_raise_memlock_limit(1 << 20).unwrap();
+      assert_eq!(SensitiveData::current_memlock_limit().unwrap(),
+                 (1 << 20, 1 << 20));
+      0
+    });
+    assert!(libc::WIFEXITED(status));
+    match libc::WEXITSTATUS(status) {
+      0 => {}
+      2 => println!("Could not lower RLIMIT_MEMLOCK, skipping"),
+      code => panic!("Child exited with {}", code),
+    }
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn hardened_once() {
+    let status = crate::tests::in_child(|| {
+      SensitiveData::auto_harden();
+      assert_eq!(AUTO_HARDENINGS.load(Ordering::Relaxed), 0);
+      for _ in 0..100 {
+        SensitiveData::new([1u8; 32]).unwrap();
+      }
+      let mut limit = libc::rlimit { rlim_cur: 1,
+                                     rlim_max: 1 };
+      unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) };
+      assert_eq!(limit.rlim_cur, 0);
+      assert_eq!(unsafe { libc::prctl(libc::PR_GET_DUMPABLE) }, 0);
+      AUTO_HARDENINGS.load(Ordering::Relaxed) as i32
+    });
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 1);
+  }
+}
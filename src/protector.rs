@@ -0,0 +1,649 @@
+use core::ffi::CStr;
+
+use crate::err::{self, IoError};
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+use crate::sys;
+
+/// The operations used to protect the memory of a `SensitiveData`. Targets
+/// without an operating system can implement this on top of whatever memory
+/// protection unit they have, and pass it to `SensitiveData::with_protector`.
+///
+/// Every method operates on `len` bytes starting at `ptr`, where `ptr` is
+/// aligned to `page_size()` and `len` is a multiple of it.
+pub trait MemoryProtector: Sync {
+  /// The granularity at which protection can be changed. Must be a power of
+  /// two.
+  fn page_size(&self) -> usize;
+
+  /// Keeps the memory from being swapped out.
+  fn lock(&self, ptr: *mut u8, len: usize) -> Result<(), IoError>;
+
+  /// Like `lock`, but the pages are only locked as they're first touched, where
+  /// supported.
+  fn lock_on_fault(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    self.lock(ptr, len)
+  }
+
+  /// Drops the contents of the memory, so that it reads as zeroes without
+  /// being backed by anything until it's written. Called before the memory is
+  /// locked. Fails where that isn't supported, in which case the memory is
+  /// zeroed instead.
+  fn discard(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Err(err::unsupported())
+  }
+
+  /// Undoes `lock` right before the memory is freed, so that allocators that
+  /// keep the pages around don't keep them locked too.
+  fn unlock(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  /// Whether `error`, as returned by `lock`, means that the limit on how much
+  /// memory may be locked was reached.
+  fn is_lock_limit(&self, _error: &IoError) -> bool {
+    false
+  }
+
+  /// Asks whether every page is locked right now, as opposed to trusting that
+  /// `lock` succeeded. Fails where that can't be found out.
+  fn verify_locked(&self, _ptr: *mut u8, _len: usize) -> Result<bool, IoError> {
+    Err(err::unsupported())
+  }
+
+  /// Keeps the memory out of core dumps, where supported.
+  fn exclude_from_dump(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  /// Undoes `exclude_from_dump` right before the memory is freed, where that
+  /// holds on to anything.
+  fn include_in_dump(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  /// Gives the memory a name that shows up when listing the mappings of the
+  /// process, where supported, or takes it away again with `None` right before
+  /// it's freed.
+  fn set_name(&self, _ptr: *mut u8, _len: usize, _name: Option<&CStr>) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  /// Makes forked children see zeroes in place of the memory, where supported.
+  fn wipe_on_fork(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  /// Undoes `wipe_on_fork` right before the memory is freed.
+  fn keep_on_fork(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  fn make_inaccessible(&self, ptr: *mut u8, len: usize) -> Result<(), IoError>;
+
+  fn make_readable(&self, ptr: *mut u8, len: usize) -> Result<(), IoError>;
+
+  fn make_writable(&self, ptr: *mut u8, len: usize) -> Result<(), IoError>;
+}
+
+/// Protects memory using the operating system, with `mlock` and `mprotect` on
+/// unix and their equivalents on Windows. This is what `SensitiveData` uses
+/// unless told otherwise.
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+pub struct OsProtector;
+
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+impl MemoryProtector for OsProtector {
+  #[inline(always)]
+  fn page_size(&self) -> usize {
+    sys::page_size()
+  }
+
+  #[inline(always)]
+  fn lock(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::lock(ptr, len)
+  }
+
+  #[inline(always)]
+  fn lock_on_fault(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::lock_on_fault(ptr, len)
+  }
+
+  #[inline(always)]
+  fn discard(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::discard(ptr, len)
+  }
+
+  #[inline(always)]
+  fn unlock(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::unlock(ptr, len)
+  }
+
+  #[inline(always)]
+  fn is_lock_limit(&self, error: &IoError) -> bool {
+    sys::is_lock_limit(error)
+  }
+
+  #[inline(always)]
+  fn verify_locked(&self, ptr: *mut u8, len: usize) -> Result<bool, IoError> {
+    sys::verify_locked(ptr, len)
+  }
+
+  #[inline(always)]
+  fn exclude_from_dump(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::exclude_from_dump(ptr, len)
+  }
+
+  #[inline(always)]
+  fn include_in_dump(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::include_in_dump(ptr, len)
+  }
+
+  #[inline(always)]
+  fn set_name(&self, ptr: *mut u8, len: usize, name: Option<&CStr>) -> Result<(), IoError> {
+    sys::set_name(ptr, len, name)
+  }
+
+  #[inline(always)]
+  fn wipe_on_fork(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::wipe_on_fork(ptr, len)
+  }
+
+  #[inline(always)]
+  fn keep_on_fork(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::keep_on_fork(ptr, len)
+  }
+
+  #[inline(always)]
+  fn make_inaccessible(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::make_inaccessible(ptr, len)
+  }
+
+  #[inline(always)]
+  fn make_readable(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::make_readable(ptr, len)
+  }
+
+  #[inline(always)]
+  fn make_writable(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+    sys::make_writable(ptr, len)
+  }
+}
+
+/// Used by default on targets without a known way to protect memory. Every
+/// operation fails, so a protector has to be given explicitly.
+#[cfg(not(any(target_family = "unix",
+              target_family = "windows",
+              target_arch = "wasm32")))]
+struct Unsupported;
+
+#[cfg(not(any(target_family = "unix",
+              target_family = "windows",
+              target_arch = "wasm32")))]
+impl MemoryProtector for Unsupported {
+  fn page_size(&self) -> usize {
+    1
+  }
+
+  fn lock(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Err(crate::err::unsupported())
+  }
+
+  fn make_inaccessible(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Err(crate::err::unsupported())
+  }
+
+  fn make_readable(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Err(crate::err::unsupported())
+  }
+
+  fn make_writable(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Err(crate::err::unsupported())
+  }
+}
+
+/// Used by default under Miri, which can't run the system calls. Everything
+/// else is the same, but nothing is actually locked or protected, so the tests
+/// that check locking, page protection, guard pages or dump exclusion with the
+/// OS are left out there.
+#[cfg(miri)]
+struct Software;
+
+#[cfg(miri)]
+impl MemoryProtector for Software {
+  fn page_size(&self) -> usize {
+    4096
+  }
+
+  fn lock(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  fn make_inaccessible(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  fn make_readable(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  fn make_writable(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+}
+
+/// Used by default on WASM, where linear memory can't be locked or protected
+/// at all. Locking fails as if the limit were zero, so that it's off by
+/// default and `new_best_effort` falls back, and the protection changes do
+/// nothing.
+#[cfg(all(not(miri), target_arch = "wasm32"))]
+struct Wasm;
+
+#[cfg(all(not(miri), target_arch = "wasm32"))]
+impl MemoryProtector for Wasm {
+  fn page_size(&self) -> usize {
+    4096
+  }
+
+  fn lock(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Err(err::unsupported())
+  }
+
+  fn is_lock_limit(&self, _error: &IoError) -> bool {
+    true
+  }
+
+  fn make_inaccessible(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  fn make_readable(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+
+  fn make_writable(&self, _ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+    Ok(())
+  }
+}
+
+#[cfg(miri)]
+#[inline(always)]
+pub(crate) fn default_protector() -> &'static dyn MemoryProtector {
+  &Software
+}
+
+#[cfg(all(not(miri), target_arch = "wasm32"))]
+#[inline(always)]
+pub(crate) fn default_protector() -> &'static dyn MemoryProtector {
+  &Wasm
+}
+
+#[cfg(all(not(miri), any(target_family = "unix", target_family = "windows")))]
+#[inline(always)]
+pub(crate) fn default_protector() -> &'static dyn MemoryProtector {
+  &OsProtector
+}
+
+#[cfg(all(not(miri),
+          not(any(target_family = "unix",
+                  target_family = "windows",
+                  target_arch = "wasm32"))))]
+#[inline(always)]
+pub(crate) fn default_protector() -> &'static dyn MemoryProtector {
+  &Unsupported
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use core::sync::atomic::{AtomicU8, Ordering};
+  use std::sync::Mutex;
+
+  use super::*;
+  use crate::{err, SensitiveData};
+
+  #[derive(Clone, Copy, Debug, PartialEq)]
+  pub(crate) enum Call {
+    Lock,
+    Unlock,
+    Inaccessible,
+    Readable,
+    Writable,
+  }
+
+  /// Records every call, and fails the ones it's told to. It protects
+  /// nothing, like an embedded target without a memory protection unit would,
+  /// unless it wraps another protector that it passes the calls on to.
+  pub(crate) struct Mock {
+    inner: Option<&'static dyn MemoryProtector>,
+    /// A bit for each `Call` that fails every time.
+    fails: AtomicU8,
+    /// A bit for each `Call` that fails the next time only.
+    fails_once: AtomicU8,
+    calls: Mutex<Vec<(Call, usize, usize)>>,
+  }
+
+  impl Mock {
+    pub(crate) const fn new() -> Self {
+      Mock { inner: None,
+             fails: AtomicU8::new(0),
+             fails_once: AtomicU8::new(0),
+             calls: Mutex::new(Vec::new()) }
+    }
+
+    #[cfg(all(not(miri), any(target_family = "unix", target_family = "windows")))]
+    pub(crate) const fn wrapping(inner: &'static dyn MemoryProtector) -> Self {
+      Mock { inner: Some(inner),
+             fails: AtomicU8::new(0),
+             fails_once: AtomicU8::new(0),
+             calls: Mutex::new(Vec::new()) }
+    }
+
+    pub(crate) fn fail(&self, call: Call) {
+      self.fails.fetch_or(1 << call as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn fail_once(&self, call: Call) {
+      self.fails_once.fetch_or(1 << call as u8, Ordering::Relaxed);
+    }
+
+    fn call(&self,
+            call: Call,
+            ptr: *mut u8,
+            len: usize,
+            pass_on: fn(&dyn MemoryProtector, *mut u8, usize) -> Result<(), IoError>)
+            -> Result<(), IoError> {
+      self.calls.lock().unwrap().push((call, ptr as usize, len));
+      let bit = 1 << call as u8;
+      if self.fails.load(Ordering::Relaxed) & bit != 0
+         || self.fails_once.fetch_and(!bit, Ordering::Relaxed) & bit != 0
+      {
+        // A protection change that fails may well leave the memory
+        // inaccessible.
+        if let (Some(inner), Call::Readable | Call::Writable) = (self.inner, call) {
+          let _ = inner.make_inaccessible(ptr, len);
+        }
+        return Err(err::unsupported());
+      }
+      self.inner.map_or(Ok(()), |inner| pass_on(inner, ptr, len))
+    }
+
+    /// Takes the calls on the value so far. Calls on guard pages are left out.
+    pub(crate) fn take(&self) -> Vec<Call> {
+      let page_size = self.page_size();
+      core::mem::take(&mut *self.calls.lock().unwrap()).into_iter()
+                                                       .filter(|(_, _, len)| *len != page_size)
+                                                       .map(|(call, _, _)| call)
+                                                       .collect()
+    }
+
+    /// How many times `call` was made, guard pages included.
+    pub(crate) fn count(&self, call: Call) -> usize {
+      self.calls
+          .lock()
+          .unwrap()
+          .iter()
+          .filter(|(made, _, _)| *made == call)
+          .count()
+    }
+
+    /// How many times the protection was changed, guard pages included.
+    pub(crate) fn changes(&self) -> usize {
+      [Call::Inaccessible, Call::Readable, Call::Writable].iter()
+                                                          .map(|call| self.count(*call))
+                                                          .sum()
+    }
+  }
+
+  impl MemoryProtector for Mock {
+    fn page_size(&self) -> usize {
+      self.inner.map_or(64, |inner| inner.page_size())
+    }
+
+    fn lock(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+      self.call(Call::Lock, ptr, len, |inner, ptr, len| inner.lock(ptr, len))
+    }
+
+    fn unlock(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+      self.call(Call::Unlock, ptr, len, |inner, ptr, len| {
+            inner.unlock(ptr, len)
+          })
+    }
+
+    fn make_inaccessible(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+      self.call(Call::Inaccessible, ptr, len, |inner, ptr, len| {
+            inner.make_inaccessible(ptr, len)
+          })
+    }
+
+    fn make_readable(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+      self.call(Call::Readable, ptr, len, |inner, ptr, len| {
+            inner.make_readable(ptr, len)
+          })
+    }
+
+    fn make_writable(&self, ptr: *mut u8, len: usize) -> Result<(), IoError> {
+      self.call(Call::Writable, ptr, len, |inner, ptr, len| {
+            inner.make_writable(ptr, len)
+          })
+    }
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  #[test]
+  fn unprotected_on_wasm() {
+    let mut a = SensitiveData::new([1u8; 32]).unwrap();
+    assert!(!a.is_locked());
+    assert!(a.is_accessible());
+    assert!(!SensitiveData::new_best_effort([1u8; 32]).unwrap()
+                                                      .is_locked());
+    assert!(matches!(crate::SensitiveDataBuilder::new().locked(true)
+                                                       .build([1u8; 32]),
+                     Err(crate::Error::LockLimitExceeded(_))));
+    a.borrow_mut()[0] = 2;
+    assert_eq!(a.borrow()[..2], [2, 1]);
+    let address = a.inner_ptr as usize;
+    drop(a);
+    assert!(crate::tests::freed_zeroized(address));
+    let mut b = SensitiveData::new([1u8; 32]).unwrap();
+    b.zeroize();
+    assert!(b.is_consumed());
+  }
+
+  #[test]
+  fn custom_protector() {
+    static UNPROTECTED: Mock = Mock::new();
+    let mut a = SensitiveData::with_protector([1u8; 100], &UNPROTECTED).unwrap();
+    assert_eq!(a.memory_layout.size(), 128);
+    a.borrow_mut()[99] = 2;
+    assert_eq!(a.borrow()[99], 2);
+  }
+
+  #[test]
+  fn failing_protector() {
+    static NO_LOCKING: Mock = Mock::new();
+    NO_LOCKING.fail(Call::Lock);
+    assert!(SensitiveData::with_protector([1u8; 100], &NO_LOCKING).is_err());
+  }
+
+  #[test]
+  fn freed_when_locking_fails() {
+    use core::sync::atomic::AtomicUsize;
+    // Fails to lock the memory, after pretending to have locked part of it.
+    static PARTIAL_LOCK: Mock = Mock::new();
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct CountsDrops(#[allow(dead_code)] u8);
+    impl Drop for CountsDrops {
+      fn drop(&mut self) {
+        DROPS.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    PARTIAL_LOCK.fail(Call::Lock);
+    crate::tests::FREED.with(|freed| freed.borrow_mut().clear());
+    assert!(SensitiveData::with_protector(CountsDrops(1), &PARTIAL_LOCK).is_err());
+    // Only the argument, since the memory never held a value.
+    assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+    assert_eq!(PARTIAL_LOCK.count(Call::Unlock), 1);
+    assert_eq!(crate::tests::FREED.with(|freed| freed.borrow().len()), 1);
+  }
+
+  #[cfg(all(not(miri), any(target_family = "unix", target_family = "windows")))]
+  #[test]
+  fn leaked_when_not_made_writable() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    static FAILS_TO_WRITE: Mock = Mock::wrapping(&OsProtector);
+    let a = SensitiveData::with_protector([1u8; 100], &FAILS_TO_WRITE).unwrap();
+    FAILS_TO_WRITE.fail(Call::Writable);
+    crate::tests::FREED.with(|freed| freed.borrow_mut().clear());
+    let dropped = catch_unwind(AssertUnwindSafe(|| drop(a)));
+    assert_eq!(dropped.is_err(), cfg!(not(feature = "abort-free-drop")));
+    // The memory is still inaccessible, so clearing or freeing it would fault.
+    assert!(crate::tests::FREED.with(|freed| freed.borrow().is_empty()));
+  }
+
+  #[cfg(all(feature = "abort-free-drop",
+            not(miri),
+            any(target_family = "unix", target_family = "windows")))]
+  #[test]
+  fn reported_when_not_made_writable() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use crate::SensitiveDataBuilder;
+    static FAILS_TO_WRITE: Mock = Mock::wrapping(&OsProtector);
+    static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    SensitiveData::on_drop_failure(|message, e| {
+      REPORTED.lock().unwrap().push(format!("{}: {}", message, e))
+    });
+    let a = SensitiveDataBuilder::new().guard_pages(true)
+                                       .protector(&FAILS_TO_WRITE)
+                                       .build([1u8; 100])
+                                       .unwrap();
+    FAILS_TO_WRITE.fail(Call::Writable);
+    crate::tests::FREED.with(|freed| freed.borrow_mut().clear());
+    assert!(catch_unwind(AssertUnwindSafe(|| drop(a))).is_ok());
+    assert!(crate::tests::FREED.with(|freed| freed.borrow().is_empty()));
+    let reported = REPORTED.lock().unwrap();
+    for failure in ["Could not make SensitiveData writable",
+                    "Could not make the guard pages writable"]
+    {
+      assert!(reported.iter()
+                      .any(|reported| reported.starts_with(failure)),
+              "{:?} not in {:?}",
+              failure,
+              reported);
+    }
+  }
+
+  #[test]
+  fn freed_when_not_unlocked() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    static FAILS_TO_UNLOCK: Mock = Mock::new();
+    let a = SensitiveData::with_protector([1u8; 100], &FAILS_TO_UNLOCK).unwrap();
+    let address = a.inner_ptr as usize;
+    FAILS_TO_UNLOCK.fail(Call::Unlock);
+    let dropped = catch_unwind(AssertUnwindSafe(|| drop(a)));
+    assert_eq!(dropped.is_err(), cfg!(not(feature = "abort-free-drop")));
+    assert!(crate::tests::freed_zeroized(address));
+    // Panicking again while unwinding would abort. Without `std` there's no
+    // telling that it's unwinding.
+    #[cfg(any(feature = "std", feature = "abort-free-drop"))]
+    {
+      let b = SensitiveData::with_protector([2u8; 100], &FAILS_TO_UNLOCK).unwrap();
+      let address = b.inner_ptr as usize;
+      assert!(catch_unwind(AssertUnwindSafe(move || {
+                             let _b = b;
+                             panic!("Unwinding with a SensitiveData");
+                           })).is_err());
+      assert!(crate::tests::freed_zeroized(address));
+    }
+  }
+
+  #[test]
+  fn handed_back_when_not_made_writable() {
+    static FIRST_WRITE_FAILS: Mock = Mock::new();
+    let a = SensitiveData::with_protector([1u8; 100], &FIRST_WRITE_FAILS).unwrap();
+    FIRST_WRITE_FAILS.fail_once(Call::Writable);
+    let (a, e) = a.try_into_inner().unwrap_err();
+    assert!(matches!(e, crate::Error::IoError(_)));
+    assert_eq!(a.borrow()[99], 1);
+    assert_eq!(a.try_into_inner().unwrap(), [1; 100]);
+  }
+
+  #[test]
+  fn transitions() {
+    static RECORDING: Mock = Mock::new();
+    let mut a = SensitiveData::with_protector([1u8; 100], &RECORDING).unwrap();
+    let range = (a.inner_ptr as usize, a.locked_size());
+    assert!(RECORDING.calls
+                     .lock()
+                     .unwrap()
+                     .iter()
+                     .filter(|(_, _, len)| *len != RECORDING.page_size())
+                     .all(|(_, ptr, len)| (*ptr, *len) == range));
+    assert_eq!(RECORDING.take(), [Call::Lock, Call::Inaccessible]);
+    assert_eq!(a.with_ref(|value| value[0]), 1);
+    assert_eq!(RECORDING.take(), [Call::Readable, Call::Inaccessible]);
+    a.with_mut(|value| value[0] = 2);
+    assert_eq!(RECORDING.take(), [Call::Writable, Call::Inaccessible]);
+    drop(a);
+    assert_eq!(RECORDING.take(), [Call::Writable, Call::Unlock]);
+  }
+
+  #[test]
+  fn redundant_changes_skipped() {
+    static COUNTING: Mock = Mock::new();
+    // Each guard page is protected when allocating, and again when freeing.
+    let guard_pages = if cfg!(feature = "guard-pages") { 2 } else { 0 };
+    let mut a = SensitiveData::with_protector([1u8; 100], &COUNTING).unwrap();
+    // Fresh memory is already writable, so it only had to be made inaccessible.
+    assert_eq!(COUNTING.changes(), guard_pages + 1);
+    {
+      let b = a.borrow();
+      let c = a.borrow();
+      assert_eq!(b[0] + b[1] + c[0], 3);
+    }
+    assert_eq!(COUNTING.changes(), guard_pages + 3);
+    a.with_mut(|value| value[0] = 2);
+    assert_eq!(COUNTING.changes(), guard_pages + 5);
+    assert_eq!(a.into_inner()[0], 2);
+    assert_eq!(COUNTING.changes(), 2 * guard_pages + 6);
+  }
+
+  #[test]
+  fn poisoned() {
+    use crate::Error;
+    static FAILS_TO_RESTORE: Mock = Mock::new();
+    let mut a = SensitiveData::with_protector([1u8; 100], &FAILS_TO_RESTORE).unwrap();
+    let mut b = SensitiveData::with_protector([1u8; 100], &FAILS_TO_RESTORE).unwrap();
+    FAILS_TO_RESTORE.fail(Call::Inaccessible);
+    assert_eq!(a.borrow()[0], 1);
+    b.borrow_mut()[0] = 2;
+    for data in [&mut a, &mut b] {
+      assert!(data.is_poisoned());
+      assert!(!data.verify());
+      assert!(matches!(data.try_borrow(), Err(Error::Poisoned)));
+      assert!(matches!(data.try_borrow_mut(), Err(Error::Poisoned)));
+      assert!(matches!(data.replace([3; 100]), Err(Error::Poisoned)));
+    }
+    let address = a.inner_ptr as usize;
+    drop(a);
+    assert!(crate::tests::freed_zeroized(address));
+  }
+
+  #[test]
+  fn both_selected_from() {
+    static RECORDING: Mock = Mock::new();
+    let a = SensitiveData::with_protector([1u8; 100], &RECORDING).unwrap();
+    let b = SensitiveData::with_protector([2u8; 100], &RECORDING).unwrap();
+    for condition in [true, false] {
+      RECORDING.take();
+      SensitiveData::ct_select(condition.into(), &a, &b).unwrap();
+      let calls = RECORDING.calls.lock().unwrap();
+      for data in [&a, &b] {
+        let address = data.inner_ptr as usize;
+        let calls = calls.iter()
+                         .filter(|(_, ptr, _)| *ptr == address)
+                         .map(|(call, _, _)| call);
+        assert!(calls.eq([&Call::Readable, &Call::Inaccessible]));
+      }
+    }
+  }
+}
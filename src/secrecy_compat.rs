@@ -0,0 +1,64 @@
+//! Conversions from the `secrecy` crate, whose secrets are only zeroized when
+//! dropped, into locked and protected memory. The value is copied out, and the
+//! source is zeroized by `secrecy` as it's dropped.
+
+use core::convert::TryFrom;
+
+use secrecy::{zeroize::Zeroize, ExposeSecret, SecretBox};
+
+use crate::{zeroize_stack, Error, SensitiveData};
+
+impl<T: Clone + Zeroize> TryFrom<SecretBox<T>> for SensitiveData<T> {
+  type Error = Error;
+
+  fn try_from(secret: SecretBox<T>) -> Result<Self, Error> {
+    let data = SensitiveData::new(secret.expose_secret().clone());
+    zeroize_stack();
+    data
+  }
+}
+
+impl TryFrom<SecretBox<str>> for SensitiveData<str> {
+  type Error = Error;
+
+  fn try_from(secret: SecretBox<str>) -> Result<Self, Error> {
+    secret.expose_secret().parse()
+  }
+}
+
+impl TryFrom<SecretBox<[u8]>> for SensitiveData<[u8]> {
+  type Error = Error;
+
+  fn try_from(secret: SecretBox<[u8]>) -> Result<Self, Error> {
+    SensitiveData::from_slice(secret.expose_secret())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use secrecy::{SecretSlice, SecretString};
+
+  use super::*;
+  use crate::{SensitiveBytes, SensitiveString};
+
+  #[test]
+  fn from_secret_string() {
+    let secret = SecretString::from("hunter2");
+    let a = SensitiveString::try_from(secret).unwrap();
+    assert_eq!(a.with_str(str::to_owned), "hunter2");
+  }
+
+  #[test]
+  fn from_secret_slice() {
+    let secret = SecretSlice::from(vec![1u8, 2, 3]);
+    let a = SensitiveBytes::try_from(secret).unwrap();
+    assert_eq!(*a.borrow(), [1, 2, 3]);
+  }
+
+  #[test]
+  fn from_secret_box() {
+    let secret = SecretBox::new(Box::new([7u8; 32]));
+    let a = SensitiveData::try_from(secret).unwrap();
+    assert_eq!(*a.borrow(), [7; 32]);
+  }
+}
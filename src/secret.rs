@@ -0,0 +1,172 @@
+use core::{marker::PhantomData, mem::ManuallyDrop, ops::Deref};
+
+use super::{Error, SensitiveData};
+
+/// The state of a `Secret` whose value can't be read.
+pub struct Sealed;
+
+/// The state of a `Secret` whose value is readable until it is sealed again.
+/// Like a `DerefHolder`, an opened secret isn't `Send`, so that the memory
+/// can't be left readable across an `.await`.
+pub struct Opened(PhantomData<*const ()>);
+
+mod private {
+  pub trait Sealing {}
+  impl Sealing for super::Sealed {}
+  impl Sealing for super::Opened {}
+}
+
+/// Either `Sealed` or `Opened`.
+pub trait State: private::Sealing {
+  #[doc(hidden)]
+  const OPENED: bool;
+}
+
+impl State for Sealed {
+  const OPENED: bool = false;
+}
+
+impl State for Opened {
+  const OPENED: bool = true;
+}
+
+/// A `SensitiveData` which tracks in its type whether the value may be read.
+/// A `Secret<T, Sealed>` has to be opened before it derefs, and the memory
+/// stays readable for exactly as long as it is a `Secret<T, Opened>`.
+///
+/// ```
+/// # use sensitive_data::Secret;
+/// let key = Secret::new([1u8; 32]).unwrap();
+/// let key = key.open();
+/// assert_eq!(key[0], 1);
+/// let key = key.seal();
+/// ```
+///
+/// ```compile_fail
+/// # use sensitive_data::Secret;
+/// let key = Secret::new([1u8; 32]).unwrap();
+/// assert_eq!(key[0], 1);
+/// ```
+///
+/// ```compile_fail
+/// # use sensitive_data::Secret;
+/// # fn require_send(_: impl Send) {}
+/// # async fn elsewhere() {}
+/// let key = Secret::new([1u8; 32]).unwrap();
+/// require_send(async move {
+///   let key = key.open();
+///   elsewhere().await;
+///   key[0]
+/// });
+/// ```
+pub struct Secret<T: ?Sized, S: State = Sealed> {
+  data: ManuallyDrop<SensitiveData<T>>,
+  _state: PhantomData<S>,
+}
+
+impl<T> Secret<T, Sealed> {
+  pub fn new(t: T) -> Result<Self, Error> {
+    SensitiveData::new(t).map(Self::from)
+  }
+}
+
+impl<T: ?Sized> From<SensitiveData<T>> for Secret<T, Sealed> {
+  fn from(data: SensitiveData<T>) -> Self {
+    Secret { data: ManuallyDrop::new(data),
+             _state: PhantomData }
+  }
+}
+
+impl<T: ?Sized, S: State> Secret<T, S> {
+  /// Moves the `SensitiveData` out, without running `Drop` for `self`.
+  fn into_data_unchecked(self) -> SensitiveData<T> {
+    let mut this = ManuallyDrop::new(self);
+    unsafe { ManuallyDrop::take(&mut this.data) }
+  }
+}
+
+impl<T: ?Sized> Secret<T, Sealed> {
+  /// Makes the value readable. Panics like `SensitiveData::borrow` if it
+  /// can't be.
+  pub fn open(self) -> Secret<T, Opened> {
+    match self.try_open() {
+      Ok(opened) => opened,
+      Err((_, e)) => panic!("Could not make SensitiveData readable: {:?}", e),
+    }
+  }
+
+  /// Like `open`, but hands the secret back, still sealed, if the value can't
+  /// be made readable.
+  pub fn try_open(self) -> Result<Secret<T, Opened>, (Self, Error)> {
+    // The read is released again by `seal`, or when the secret is dropped.
+    if let Err(e) = self.data.try_borrow().map(core::mem::forget) {
+      return Err((self, e));
+    }
+    Ok(Secret { data: ManuallyDrop::new(self.into_data_unchecked()),
+                _state: PhantomData })
+  }
+
+  pub fn into_data(self) -> SensitiveData<T> {
+    self.into_data_unchecked()
+  }
+}
+
+impl<T: ?Sized> Secret<T, Opened> {
+  /// Makes the memory inaccessible again. If that fails, the secret is
+  /// poisoned, just like when a borrow is dropped.
+  pub fn seal(self) -> Secret<T, Sealed> {
+    let data = self.into_data_unchecked();
+    if data.release_read().is_err() {
+      data.poison();
+    }
+    Secret::from(data)
+  }
+}
+
+impl<T: ?Sized> Deref for Secret<T, Opened> {
+  type Target = T;
+  fn deref(&self) -> &Self::Target {
+    unsafe { &(*self.data.inner_ptr).value }
+  }
+}
+
+impl<T: ?Sized, S: State> Drop for Secret<T, S> {
+  fn drop(&mut self) {
+    if S::OPENED && self.data.release_read().is_err() {
+      self.data.poison();
+    }
+    unsafe { ManuallyDrop::drop(&mut self.data) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(target_family = "unix")]
+  use crate::tests::is_inaccessible;
+
+  #[test]
+  fn round_trip() {
+    let secret = Secret::new([1u8; 32]).unwrap();
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&secret.data));
+    let secret = secret.open();
+    assert_eq!(*secret, [1u8; 32]);
+    #[cfg(target_family = "unix")]
+    assert!(!is_inaccessible(&secret.data));
+    let secret = secret.seal();
+    #[cfg(target_family = "unix")]
+    assert!(is_inaccessible(&secret.data));
+    let secret = secret.open();
+    assert_eq!(secret.iter().map(|b| u32::from(*b)).sum::<u32>(), 32);
+    let data = secret.seal().into_data();
+    assert_eq!(*data.borrow(), [1u8; 32]);
+  }
+
+  #[test]
+  fn dropped_while_opened() {
+    let secret = Secret::new([1u8; 32]).unwrap().open();
+    assert!(!secret.data.is_poisoned());
+    drop(secret);
+  }
+}
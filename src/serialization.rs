@@ -0,0 +1,70 @@
+//! Serde support. `Deserialize` is enabled by the `serde` feature, and moves
+//! the value into locked memory as soon as it has been deserialized.
+//! `Serialize` needs the separate `serde-serialize` feature, since it copies
+//! the plaintext into whatever the serializer writes to, outside of any
+//! protection this crate can offer.
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+#[cfg(feature = "serde-serialize")]
+use serde::{ser::Error as _, Serialize, Serializer};
+
+use crate::{zeroize_stack, SensitiveData};
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SensitiveData<T> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let data = SensitiveData::new(T::deserialize(deserializer)?).map_err(D::Error::custom);
+    zeroize_stack();
+    data
+  }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: ?Sized + Serialize> Serialize for SensitiveData<T> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    // Like `with_ref`, which can't hand back the error.
+    #[cfg(all(feature = "block-signals", target_family = "unix"))]
+    let _blocked = crate::process::block_signals();
+    let result = self.try_borrow()
+                     .map_err(S::Error::custom)?
+                     .serialize(serializer);
+    zeroize_stack();
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::SensitiveData;
+
+  #[test]
+  fn deserialize() {
+    let a: SensitiveData<[u8; 4]> = serde_json::from_str("[1, 2, 3, 4]").unwrap();
+    assert_eq!(*a.borrow(), [1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn deserialize_error() {
+    assert!(serde_json::from_str::<SensitiveData<[u8; 4]>>("[1, 2, 3]").is_err());
+  }
+
+  #[cfg(feature = "serde-serialize")]
+  #[test]
+  fn serialize_error() {
+    use crate::protector::tests::{Call, Mock};
+    static FAILS_TO_READ: Mock = Mock::new();
+    let a = SensitiveData::with_protector([1u8; 4], &FAILS_TO_READ).unwrap();
+    FAILS_TO_READ.fail(Call::Readable);
+    assert!(serde_json::to_string(&a).is_err());
+  }
+
+  #[cfg(feature = "serde-serialize")]
+  #[test]
+  fn serialize() {
+    let a: SensitiveData<[u8; 4]> = SensitiveData::new([1, 2, 3, 4]).unwrap();
+    assert_eq!(serde_json::to_string(&a).unwrap(), "[1,2,3,4]");
+    assert_eq!(a.state
+                .deref_counter
+                .load(std::sync::atomic::Ordering::Acquire),
+               0);
+  }
+}
@@ -0,0 +1,122 @@
+use core::{alloc::Layout, ptr::slice_from_raw_parts_mut};
+
+use super::{page_aligned, Error, HolderInner, Options, SensitiveData};
+
+/// Sensitive values of any type, with a length only known at runtime, such as
+/// the round keys of a key schedule.
+pub type SensitiveSlice<T> = SensitiveData<[T]>;
+
+impl<T> SensitiveData<[T]> {
+  /// The layout of `len` elements, rounded up to whole pages. Fails with
+  /// `Error::TooLarge` if that's more than `isize::MAX` bytes, which `Layout`
+  /// checks for every step of the way, so none of it can overflow.
+  pub(super) fn layout(len: usize, page_size: usize) -> Result<Layout, Error> {
+    const {
+      assert!(core::mem::size_of::<T>() != 0,
+              "SensitiveData can't hold a zero sized type")
+    };
+    // An empty slice still gets a page, since zero sized allocations are not
+    // allowed.
+    Layout::array::<T>(len.max(1)).and_then(|layout| page_aligned(layout, page_size))
+                                  .map_err(|_| Error::TooLarge)
+  }
+
+  /// Creates `len` default values, in memory that's zeroized first.
+  pub fn with_len(len: usize) -> Result<Self, Error>
+    where T: Default
+  {
+    let options = Options::default();
+    // The slice starts out empty, so that a panicking `T::default` leaks the
+    // values written so far instead of dropping uninitialized ones.
+    let mut holder =
+      Self::allocate_holder(Self::layout(len, options.protector.page_size())?,
+                            len * core::mem::size_of::<T>(),
+                            options,
+                            |ptr| slice_from_raw_parts_mut(ptr, 0) as *mut HolderInner<[T]>)?;
+    holder.zeroize_inner();
+    let first = holder.inner_ptr as *mut T;
+    for i in 0..len {
+      unsafe { first.add(i).write(T::default()) };
+    }
+    holder.inner_ptr = slice_from_raw_parts_mut(first, len) as *mut HolderInner<[T]>;
+    holder.make_inaccessible()
+          .expect("Could not make the new SensitiveData inaccessible");
+    Ok(holder)
+  }
+
+  #[inline(always)]
+  pub fn len(&self) -> usize {
+    (self.inner_ptr as *mut [T]).len()
+  }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+  use crate::tests::page_size;
+
+  #[test]
+  fn too_large() {
+    let max_len = isize::MAX as usize / 8;
+    for len in [max_len - 1, max_len, max_len + 1, usize::MAX] {
+      assert!(matches!(SensitiveSlice::<u64>::with_len(len), Err(Error::TooLarge)),
+              "{} elements",
+              len);
+    }
+    assert_eq!(Error::TooLarge.to_string(),
+               "the requested length exceeds isize::MAX bytes once page-rounded");
+    // Fits when rounded up to pages, but not with the guard pages around it.
+    let largest = (isize::MAX as usize + 1 - page_size()) / 8;
+    assert!(SensitiveSlice::<u64>::layout(largest, page_size()).is_ok());
+    let a = SensitiveSlice::<u64>::with_len(largest);
+    if cfg!(feature = "guard-pages") {
+      assert!(matches!(a, Err(Error::TooLarge)));
+    } else {
+      assert!(matches!(a, Err(Error::AllocError(_))));
+    }
+  }
+
+  #[test]
+  fn spans_two_pages() {
+    let len = page_size() / 8 + 1;
+    let mut a = SensitiveSlice::<u64>::with_len(len).unwrap();
+    assert_eq!(a.len(), len);
+    assert_eq!(a.memory_layout.size(), 2 * page_size());
+    assert!(a.borrow().iter().all(|n| *n == 0));
+    a.borrow_mut()[len - 1] = u64::MAX;
+    assert_eq!(a.borrow()[len - 1], u64::MAX);
+    assert_eq!(a.borrow()[..len - 1].iter().sum::<u64>(), 0);
+  }
+
+  #[test]
+  fn elements_dropped() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    #[derive(Default)]
+    struct Counted(#[allow(dead_code)] u8);
+    impl Drop for Counted {
+      fn drop(&mut self) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    let a = SensitiveSlice::<Counted>::with_len(3).unwrap();
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+    let address = a.inner_ptr as *mut u8 as usize;
+    drop(a);
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 3);
+    assert!(crate::tests::freed_zeroized(address));
+  }
+
+  #[test]
+  fn empty() {
+    let a = SensitiveSlice::<u64>::with_len(0).unwrap();
+    assert!(a.is_empty());
+    assert_eq!(a.memory_layout.size(), page_size());
+  }
+}
@@ -0,0 +1,70 @@
+use core::{fmt, str::FromStr};
+
+use super::{Error, HolderInner, SensitiveBytes, SensitiveData};
+
+/// A sensitive string. Unlike `SensitiveData<String>`, the characters
+/// themselves are stored in the protected memory.
+pub type SensitiveString = SensitiveData<str>;
+
+impl FromStr for SensitiveData<str> {
+  type Err = Error;
+
+  /// Creates a copy of `s`. The source is left untouched, so it's up to the
+  /// caller to clear it.
+  fn from_str(s: &str) -> Result<Self, Error> {
+    Ok(unsafe { Self::from_utf8_unchecked(SensitiveBytes::from_slice(s.as_bytes())?) })
+  }
+}
+
+impl SensitiveData<str> {
+  /// # Safety
+  /// The bytes must be valid UTF-8.
+  unsafe fn from_utf8_unchecked(bytes: SensitiveBytes) -> Self {
+    bytes.cast(|ptr| ptr as *mut HolderInner<str>)
+  }
+
+  /// The length in bytes.
+  #[inline(always)]
+  pub fn len(&self) -> usize {
+    (self.inner_ptr as *mut [u8]).len()
+  }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Runs `f` with the string readable, and makes it inaccessible again before
+  /// returning.
+  pub fn with_str<R>(&self, f: impl FnOnce(&str) -> R) -> R {
+    self.with_ref(f)
+  }
+}
+
+impl fmt::Display for SensitiveData<str> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "[redacted]")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn copied_from_str() {
+    let a = SensitiveString::from_str("pässwörd").unwrap();
+    assert_eq!(a.len(), "pässwörd".len());
+    assert!(a.with_str(|value| value == "pässwörd"));
+    assert_eq!(&*a.borrow(), "pässwörd");
+    assert!(SensitiveString::from_str("").unwrap().is_empty());
+  }
+
+  #[test]
+  fn redacted() {
+    let a: SensitiveString = "hunter2".parse().unwrap();
+    assert_eq!(format!("{}", a), "[redacted]");
+    assert!(!format!("{:?}", a).contains("hunter2"));
+    assert!(!format!("{:?}", a.borrow()).contains("hunter2"));
+  }
+}
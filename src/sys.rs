@@ -0,0 +1,642 @@
+//! Platform specific memory management. Every function operates on `len` bytes
+//! starting at the page aligned `ptr`.
+
+use core::{
+  ffi::CStr,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+#[cfg(test)]
+use std::cell::Cell;
+
+#[cfg(target_family = "unix")]
+use libc::c_void;
+#[cfg(target_family = "windows")]
+use winapi::{
+  ctypes::c_void,
+  um::{memoryapi, sysinfoapi, winnt},
+};
+
+use crate::err::IoError;
+
+#[cfg(test)]
+std::thread_local! {
+  /// Makes `lock` fail with this OS error, for testing error paths.
+  pub(crate) static FAIL_LOCK: Cell<Option<i32>> = const { Cell::new(None) };
+  /// Makes `mlock2` fail with this OS error, such as `ENOSYS` to pretend that
+  /// the kernel is too old to have it.
+  pub(crate) static FAIL_MLOCK2: Cell<Option<i32>> = const { Cell::new(None) };
+}
+
+/// Returns the injected failure, if any.
+#[cfg(test)]
+fn injected_failure(failure: &'static std::thread::LocalKey<Cell<Option<i32>>>)
+                    -> Result<(), IoError> {
+  match failure.with(Cell::get) {
+    Some(error) => Err(IoError::from_raw_os_error(error)),
+    None => Ok(()),
+  }
+}
+
+/// The page size, once it has been asked for. 0 until then.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Asks the OS for the page size only the first time, since it's needed for
+/// every allocation and every change of protection.
+#[inline(always)]
+pub(crate) fn page_size() -> usize {
+  match PAGE_SIZE.load(Ordering::Relaxed) {
+    0 => {
+      let page_size = query_page_size();
+      PAGE_SIZE.store(page_size, Ordering::Relaxed);
+      page_size
+    }
+    page_size => {
+      // Every allocation is laid out with the page size, so it must not change
+      // underneath them.
+      debug_assert_eq!(page_size, query_page_size(), "The page size changed");
+      page_size
+    }
+  }
+}
+
+#[cfg(target_family = "unix")]
+fn query_page_size() -> usize {
+  (unsafe { libc::sysconf(libc::_SC_PAGESIZE) }) as usize
+}
+
+#[cfg(target_family = "windows")]
+fn query_page_size() -> usize {
+  let mut system_info = sysinfoapi::SYSTEM_INFO::default();
+  unsafe { sysinfoapi::GetSystemInfo(&mut system_info as *mut _) };
+  system_info.dwPageSize as usize
+}
+
+#[cfg(target_family = "unix")]
+#[inline(always)]
+pub(crate) fn lock(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  #[cfg(test)]
+  injected_failure(&FAIL_LOCK)?;
+  if unsafe { libc::mlock(ptr as *mut c_void, len) } == 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+/// Locks the memory, growing the working set once if it's too small to hold
+/// it. Only pages in the working set can be locked, and the default minimum
+/// is only a few hundred kilobytes.
+#[cfg(target_family = "windows")]
+pub(crate) fn lock(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  use winapi::shared::winerror::ERROR_WORKING_SET_QUOTA;
+  #[cfg(test)]
+  injected_failure(&FAIL_LOCK)?;
+  let virtual_lock = || {
+    if unsafe { memoryapi::VirtualLock(ptr as *mut c_void, len) } != 0 {
+      Ok(())
+    } else {
+      Err(IoError::last_os_error())
+    }
+  };
+  match virtual_lock() {
+    Err(error) if error.raw_os_error() == Some(ERROR_WORKING_SET_QUOTA as i32) => {
+      grow_working_set(len).map_err(|_| error)?;
+      virtual_lock()
+    }
+    result => result,
+  }
+}
+
+/// Raises both the minimum and maximum working set of the process by `len`.
+#[cfg(target_family = "windows")]
+fn grow_working_set(len: usize) -> Result<(), IoError> {
+  use winapi::um::processthreadsapi::GetCurrentProcess;
+  let (mut minimum, mut maximum, mut flags) = (0, 0, 0);
+  unsafe {
+    let process = GetCurrentProcess();
+    if memoryapi::GetProcessWorkingSetSizeEx(process, &mut minimum, &mut maximum, &mut flags) == 0
+       || memoryapi::SetProcessWorkingSetSizeEx(process,
+                                                minimum.saturating_add(len),
+                                                maximum.saturating_add(len),
+                                                flags)
+          == 0
+    {
+      return Err(IoError::last_os_error());
+    }
+  }
+  Ok(())
+}
+
+/// Locks the memory as it's faulted in, instead of faulting all of it in up
+/// front. Kernels older than 4.4 don't have `mlock2`, in which case it's locked
+/// with `mlock`. It's called through `syscall`, since older C libraries don't
+/// wrap it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn lock_on_fault(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  #[cfg(test)]
+  injected_failure(&FAIL_LOCK)?;
+  let mlock2 = || {
+    #[cfg(test)]
+    injected_failure(&FAIL_MLOCK2)?;
+    if unsafe { libc::syscall(libc::SYS_mlock2, ptr, len, libc::MLOCK_ONFAULT) } == 0 {
+      Ok(())
+    } else {
+      Err(IoError::last_os_error())
+    }
+  };
+  match mlock2() {
+    Err(error) if error.raw_os_error() == Some(libc::ENOSYS) => lock(ptr, len),
+    result => result,
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline(always)]
+pub(crate) fn lock_on_fault(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  lock(ptr, len)
+}
+
+/// Private anonymous pages given `MADV_DONTNEED` are freed, and read as zeroes
+/// until they're faulted in again. Other systems only promise that for some
+/// mappings, if at all.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn discard(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  if unsafe { libc::madvise(ptr as *mut c_void, len, libc::MADV_DONTNEED) } == 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline(always)]
+pub(crate) fn discard(_ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+  Err(crate::err::unsupported())
+}
+
+#[cfg(target_family = "unix")]
+#[inline(always)]
+pub(crate) fn unlock(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  if unsafe { libc::munlock(ptr as *mut c_void, len) } == 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(target_family = "windows")]
+#[inline(always)]
+pub(crate) fn unlock(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  if unsafe { memoryapi::VirtualUnlock(ptr as *mut c_void, len) } != 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+/// Whether `lock` failed because of the limits on how much memory may be
+/// locked, as opposed to being used incorrectly.
+#[cfg(target_family = "unix")]
+pub(crate) fn is_lock_limit(error: &IoError) -> bool {
+  matches!(error.raw_os_error(),
+           Some(libc::EPERM) | Some(libc::ENOMEM) | Some(libc::EAGAIN))
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn is_lock_limit(error: &IoError) -> bool {
+  use winapi::shared::winerror;
+  matches!(error.raw_os_error().map(|e| e as u32),
+           Some(winerror::ERROR_WORKING_SET_QUOTA)
+           | Some(winerror::ERROR_NOT_ENOUGH_MEMORY)
+           | Some(winerror::ERROR_NO_SYSTEM_RESOURCES))
+}
+
+/// Whether every page is locked, as told by `VmFlags` in `/proc/self/smaps`.
+/// The range may span several mappings, such as when parts of it have
+/// different protection.
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+pub(crate) fn verify_locked(ptr: *mut u8, len: usize) -> Result<bool, IoError> {
+  let smaps = std::fs::read_to_string("/proc/self/smaps")?;
+  let (start, end) = (ptr as usize, ptr as usize + len);
+  // How far the locked mappings cover the range so far.
+  let mut covered = start;
+  let mut overlapping = false;
+  for line in smaps.lines() {
+    if let Some(flags) = line.strip_prefix("VmFlags:") {
+      if overlapping && !flags.split_whitespace().any(|flag| flag == "lo") {
+        return Ok(false);
+      }
+    } else if let Some((first, last)) = line.split_whitespace()
+                                            .next()
+                                            .and_then(|range| range.split_once('-'))
+    {
+      if let (Ok(first), Ok(last)) =
+        (usize::from_str_radix(first, 16), usize::from_str_radix(last, 16))
+      {
+        overlapping = first < end && last > start;
+        if overlapping {
+          if first > covered {
+            return Ok(false);
+          }
+          covered = last;
+        }
+      }
+    }
+  }
+  Ok(covered >= end)
+}
+
+/// Whether every page is locked, as told by `QueryWorkingSetEx`.
+#[cfg(target_family = "windows")]
+pub(crate) fn verify_locked(ptr: *mut u8, len: usize) -> Result<bool, IoError> {
+  use alloc::vec::Vec;
+
+  use winapi::um::{
+    processthreadsapi::GetCurrentProcess,
+    psapi::{K32QueryWorkingSetEx, PSAPI_WORKING_SET_EX_INFORMATION},
+  };
+  let page_size = page_size();
+  let mut pages =
+    (0..len / page_size).map(|page| PSAPI_WORKING_SET_EX_INFORMATION { VirtualAddress:
+                                                                         unsafe {
+                                                                           ptr.add(page * page_size)
+                                                                         }
+                                                                         as *mut c_void,
+                                                                       ..Default::default() })
+                        .collect::<Vec<_>>();
+  let size = pages.len() * core::mem::size_of::<PSAPI_WORKING_SET_EX_INFORMATION>();
+  if unsafe {
+    K32QueryWorkingSetEx(GetCurrentProcess(),
+                         pages.as_mut_ptr() as *mut c_void,
+                         size as u32)
+  } == 0
+  {
+    return Err(IoError::last_os_error());
+  }
+  Ok(pages.iter()
+          .all(|page| page.VirtualAttributes.Valid() == 1 && page.VirtualAttributes.Locked() == 1))
+}
+
+#[cfg(not(any(all(feature = "std", any(target_os = "linux", target_os = "android")),
+              target_family = "windows")))]
+#[inline(always)]
+pub(crate) fn verify_locked(_ptr: *mut u8, _len: usize) -> Result<bool, IoError> {
+  Err(crate::err::unsupported())
+}
+
+/// Keeps the memory out of core dumps. Kernels too old to know the advice
+/// reject it with `EINVAL`, in which case it's silently skipped.
+#[cfg(any(target_os = "linux",
+          target_os = "android",
+          target_os = "freebsd",
+          target_os = "dragonfly"))]
+pub(crate) fn exclude_from_dump(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  const ADVICE: libc::c_int = libc::MADV_DONTDUMP;
+  #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+  const ADVICE: libc::c_int = libc::MADV_NOCORE;
+  if unsafe { libc::madvise(ptr as *mut c_void, len, ADVICE) } == 0 {
+    Ok(())
+  } else {
+    let error = IoError::last_os_error();
+    if error.raw_os_error() == Some(libc::EINVAL) {
+      Ok(())
+    } else {
+      Err(error)
+    }
+  }
+}
+
+/// Looks up `name` in kernel32, since the Windows Error Reporting functions
+/// are too new to link against directly.
+#[cfg(target_family = "windows")]
+fn kernel32_function(name: &[u8]) -> Option<winapi::shared::minwindef::FARPROC> {
+  use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+  debug_assert_eq!(name.last(), Some(&0), "The name isn't nul terminated");
+  unsafe {
+    let kernel32 = GetModuleHandleA(b"kernel32.dll\0".as_ptr() as *const i8);
+    if kernel32.is_null() {
+      return None;
+    }
+    let function = GetProcAddress(kernel32, name.as_ptr() as *const i8);
+    if function.is_null() {
+      None
+    } else {
+      Some(function)
+    }
+  }
+}
+
+/// Keeps the memory out of the dumps taken by Windows Error Reporting. Windows
+/// versions without `WerRegisterExcludedMemoryBlock` are silently skipped, as
+/// is running out of registrations, since the memory is protected either way.
+#[cfg(target_family = "windows")]
+pub(crate) fn exclude_from_dump(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  use core::convert::TryFrom;
+  type Register = unsafe extern "system" fn(*const c_void, u32) -> i32;
+  let len = match u32::try_from(len) {
+    Ok(len) => len,
+    Err(_) => return Ok(()),
+  };
+  if let Some(register) = kernel32_function(b"WerRegisterExcludedMemoryBlock\0") {
+    let register: Register = unsafe { core::mem::transmute(register) };
+    unsafe { register(ptr as *const c_void, len) };
+  }
+  Ok(())
+}
+
+/// Undoes `exclude_from_dump`, before the memory is freed.
+#[cfg(target_family = "windows")]
+pub(crate) fn include_in_dump(ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+  type Unregister = unsafe extern "system" fn(*const c_void) -> i32;
+  if let Some(unregister) = kernel32_function(b"WerUnregisterExcludedMemoryBlock\0") {
+    let unregister: Unregister = unsafe { core::mem::transmute(unregister) };
+    unsafe { unregister(ptr as *const c_void) };
+  }
+  Ok(())
+}
+
+#[cfg(not(any(target_os = "linux",
+              target_os = "android",
+              target_os = "freebsd",
+              target_os = "dragonfly",
+              target_family = "windows")))]
+#[inline(always)]
+pub(crate) fn exclude_from_dump(_ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+  Ok(())
+}
+
+#[cfg(target_family = "unix")]
+#[inline(always)]
+pub(crate) fn include_in_dump(_ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+  Ok(())
+}
+
+/// Applies `advice` about forking. Kernels older than 4.14 don't know it, and
+/// reject it with `EINVAL`, in which case it's silently skipped.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn advise_fork(ptr: *mut u8, len: usize, advice: libc::c_int) -> Result<(), IoError> {
+  if unsafe { libc::madvise(ptr as *mut c_void, len, advice) } == 0 {
+    Ok(())
+  } else {
+    let error = IoError::last_os_error();
+    if error.raw_os_error() == Some(libc::EINVAL) {
+      Ok(())
+    } else {
+      Err(error)
+    }
+  }
+}
+
+/// Makes forked children see zeroes in place of the memory.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn wipe_on_fork(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  advise_fork(ptr, len, libc::MADV_WIPEONFORK)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn keep_on_fork(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  advise_fork(ptr, len, libc::MADV_KEEPONFORK)
+}
+
+/// Names the memory, which then shows up as `[anon:<name>]` in
+/// `/proc/<pid>/maps`. Needs Linux 5.17 built with `CONFIG_ANON_VMA_NAME`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_name(ptr: *mut u8, len: usize, name: Option<&CStr>) -> Result<(), IoError> {
+  let name = name.map_or(core::ptr::null(), CStr::as_ptr);
+  if unsafe {
+    libc::prctl(libc::PR_SET_VMA,
+                libc::PR_SET_VMA_ANON_NAME as libc::c_ulong,
+                ptr as libc::c_ulong,
+                len as libc::c_ulong,
+                name as libc::c_ulong)
+  } == 0
+  {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline(always)]
+pub(crate) fn set_name(_ptr: *mut u8, _len: usize, _name: Option<&CStr>) -> Result<(), IoError> {
+  Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline(always)]
+pub(crate) fn wipe_on_fork(_ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+  Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline(always)]
+pub(crate) fn keep_on_fork(_ptr: *mut u8, _len: usize) -> Result<(), IoError> {
+  Ok(())
+}
+
+#[cfg(target_family = "unix")]
+#[inline(always)]
+fn protect(ptr: *mut u8, len: usize, protection: libc::c_int) -> Result<(), IoError> {
+  if unsafe { libc::mprotect(ptr as *mut c_void, len, protection) } == 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(target_family = "windows")]
+#[inline(always)]
+fn protect(ptr: *mut u8, len: usize, protection: u32) -> Result<(), IoError> {
+  use core::ptr::addr_of_mut;
+  if unsafe {
+    let mut _old_protect = 0;
+    memoryapi::VirtualProtect(ptr as *mut c_void,
+                              len,
+                              protection,
+                              addr_of_mut!(_old_protect))
+  } != 0
+  {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(target_family = "unix")]
+pub(crate) fn make_inaccessible(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  protect(ptr, len, libc::PROT_NONE)
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn make_inaccessible(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  protect(ptr, len, winnt::PAGE_NOACCESS)
+}
+
+#[cfg(target_family = "unix")]
+pub(crate) fn make_readable(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  protect(ptr, len, libc::PROT_READ)
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn make_readable(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  protect(ptr, len, winnt::PAGE_READONLY)
+}
+
+#[cfg(target_family = "unix")]
+pub(crate) fn make_writable(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  protect(ptr, len, libc::PROT_READ | libc::PROT_WRITE)
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn make_writable(ptr: *mut u8, len: usize) -> Result<(), IoError> {
+  protect(ptr, len, winnt::PAGE_READWRITE)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn harden_process() -> Result<(), IoError> {
+  if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } == 0 {
+    disable_core_dumps()
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(all(target_family = "unix",
+          not(any(target_os = "linux", target_os = "android"))))]
+#[inline(always)]
+pub(crate) fn harden_process() -> Result<(), IoError> {
+  disable_core_dumps()
+}
+
+#[cfg(target_family = "windows")]
+#[inline(always)]
+pub(crate) fn harden_process() -> Result<(), IoError> {
+  Ok(())
+}
+
+/// Lowers the soft limit on the size of core dumps to nothing. The hard limit
+/// is left alone, so that it can be raised again.
+#[cfg(target_family = "unix")]
+fn disable_core_dumps() -> Result<(), IoError> {
+  let mut limit = libc::rlimit { rlim_cur: 0,
+                                 rlim_max: 0 };
+  if unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+    return Err(IoError::last_os_error());
+  }
+  limit.rlim_cur = 0;
+  if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } == 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+/// Converts a resource limit to bytes, with `u64::MAX` for unlimited.
+#[cfg(target_family = "unix")]
+// `rlim_t` is only `u64` on some platforms.
+#[allow(clippy::unnecessary_cast)]
+fn from_rlim(limit: libc::rlim_t) -> u64 {
+  if limit == libc::RLIM_INFINITY {
+    u64::MAX
+  } else {
+    limit as u64
+  }
+}
+
+#[cfg(target_family = "unix")]
+fn memlock_rlimit() -> Result<libc::rlimit, IoError> {
+  let mut limit = libc::rlimit { rlim_cur: 0,
+                                 rlim_max: 0 };
+  if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } == 0 {
+    Ok(limit)
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(target_family = "unix")]
+pub(crate) fn memlock_limit() -> Result<(u64, u64), IoError> {
+  let limit = memlock_rlimit()?;
+  Ok((from_rlim(limit.rlim_cur), from_rlim(limit.rlim_max)))
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn memlock_limit() -> Result<(u64, u64), IoError> {
+  Err(crate::err::unsupported())
+}
+
+/// Raises the soft limit on locked memory to `soft`. The hard limit is left
+/// alone, so the kernel refuses anything above it.
+#[cfg(target_family = "unix")]
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn raise_memlock_limit(soft: u64) -> Result<(), IoError> {
+  let mut limit = memlock_rlimit()?;
+  if from_rlim(limit.rlim_cur) >= soft {
+    return Ok(());
+  }
+  limit.rlim_cur = if soft == u64::MAX {
+    libc::RLIM_INFINITY
+  } else {
+    soft as libc::rlim_t
+  };
+  if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) } == 0 {
+    Ok(())
+  } else {
+    Err(IoError::last_os_error())
+  }
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn raise_memlock_limit(_soft: u64) -> Result<(), IoError> {
+  Err(crate::err::unsupported())
+}
+
+/// Turns `signals` into a mask with bit `n - 1` set for signal `n`. Fails with
+/// `EINVAL` for anything that isn't a signal.
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+pub(crate) fn signal_mask(signals: &[i32]) -> Result<u64, IoError> {
+  let mut set = core::mem::MaybeUninit::<libc::sigset_t>::uninit();
+  let mut mask = 0;
+  unsafe { libc::sigemptyset(set.as_mut_ptr()) };
+  for &signal in signals {
+    if !(1..=64).contains(&signal) || unsafe { libc::sigaddset(set.as_mut_ptr(), signal) } != 0 {
+      return Err(IoError::from_raw_os_error(libc::EINVAL));
+    }
+    mask |= 1 << (signal - 1);
+  }
+  Ok(mask)
+}
+
+/// The signal mask of the calling thread from before `block_signals`, which is
+/// put back when this is dropped.
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+pub(crate) struct BlockedSignals(libc::sigset_t);
+
+/// Blocks the signals in `mask`, from `signal_mask`, in the calling thread
+/// until the returned value is dropped.
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+pub(crate) fn block_signals(mask: u64) -> Option<BlockedSignals> {
+  if mask == 0 {
+    return None;
+  }
+  let mut set = core::mem::MaybeUninit::<libc::sigset_t>::uninit();
+  let mut old = core::mem::MaybeUninit::<libc::sigset_t>::uninit();
+  unsafe {
+    libc::sigemptyset(set.as_mut_ptr());
+    for signal in (1..=64).filter(|signal| mask & (1 << (signal - 1)) != 0) {
+      libc::sigaddset(set.as_mut_ptr(), signal);
+    }
+    // Only an invalid first argument makes it fail.
+    libc::pthread_sigmask(libc::SIG_BLOCK, set.as_ptr(), old.as_mut_ptr());
+    Some(BlockedSignals(old.assume_init()))
+  }
+}
+
+#[cfg(all(feature = "block-signals", target_family = "unix"))]
+impl Drop for BlockedSignals {
+  fn drop(&mut self) {
+    unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &self.0, core::ptr::null_mut()) };
+  }
+}
@@ -0,0 +1,149 @@
+use super::{zeroize, Error, Options, SensitiveBytes};
+
+/// A growable buffer of sensitive bytes, for secrets that are built up
+/// incrementally such as a passphrase read from a terminal. The capacity always
+/// covers whole pages, and growing moves the contents to a new locked
+/// allocation and zeroizes the old one before it's freed.
+#[derive(Debug)]
+pub struct SensitiveVec {
+  buffer: SensitiveBytes,
+  len: usize,
+}
+
+impl SensitiveVec {
+  /// Creates an empty buffer, with room for a page of bytes.
+  pub fn new() -> Result<Self, Error> {
+    Self::with_capacity(0)
+  }
+
+  /// Creates an empty buffer with room for at least `capacity` bytes.
+  pub fn with_capacity(capacity: usize) -> Result<Self, Error> {
+    Ok(SensitiveVec { buffer: Self::allocate(capacity)?,
+                      len: 0 })
+  }
+
+  /// Allocates a buffer with at least `capacity` bytes, rounded up to whole
+  /// pages.
+  fn allocate(capacity: usize) -> Result<SensitiveBytes, Error> {
+    let page_size = Options::default().protector.page_size();
+    let capacity = SensitiveBytes::layout(capacity, page_size)?.size();
+    SensitiveBytes::with_len(capacity)
+  }
+
+  /// Makes room for at least `additional` more bytes.
+  pub fn reserve(&mut self, additional: usize) -> Result<(), Error> {
+    // Too much to allocate either way, so saturating makes the layout fail.
+    let required = self.len.saturating_add(additional);
+    if required <= self.capacity() {
+      return Ok(());
+    }
+    let mut buffer = Self::allocate(required.max(self.capacity().saturating_mul(2)))?;
+    let len = self.len;
+    self.buffer
+        .with_ref(|old| buffer.borrow_mut()[..len].copy_from_slice(&old[..len]));
+    // Dropping the old buffer zeroizes it before it's freed.
+    self.buffer = buffer;
+    Ok(())
+  }
+
+  pub fn push(&mut self, byte: u8) -> Result<(), Error> {
+    self.extend_from_slice(&[byte])
+  }
+
+  pub fn extend_from_slice(&mut self, bytes: &[u8]) -> Result<(), Error> {
+    self.reserve(bytes.len())?;
+    let len = self.len;
+    self.buffer.borrow_mut()[len..len + bytes.len()].copy_from_slice(bytes);
+    self.len += bytes.len();
+    Ok(())
+  }
+
+  /// Zeroizes the contents and sets the length to zero. The capacity is kept.
+  pub fn clear(&mut self) {
+    let len = self.len;
+    self.buffer.with_mut(|buffer| zeroize(&mut buffer[..len]));
+    self.len = 0;
+  }
+
+  #[inline(always)]
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  #[inline(always)]
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// The number of bytes that fit without growing.
+  #[inline(always)]
+  pub fn capacity(&self) -> usize {
+    self.buffer.len()
+  }
+
+  /// Runs `f` with the contents readable, and makes them inaccessible again
+  /// before returning.
+  pub fn with_ref<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+    self.buffer.with_ref(|buffer| f(&buffer[..self.len]))
+  }
+
+  /// Runs `f` with the contents writable, and makes them inaccessible again
+  /// before returning.
+  pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+    let len = self.len;
+    self.buffer.with_mut(|buffer| f(&mut buffer[..len]))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tests::{freed_zeroized, page_size};
+
+  #[test]
+  fn push_and_extend() {
+    let mut a = SensitiveVec::new().unwrap();
+    assert!(a.is_empty());
+    assert_eq!(a.capacity(), page_size());
+    a.push(b's').unwrap();
+    a.extend_from_slice(b"ecret").unwrap();
+    assert_eq!(a.len(), 6);
+    a.with_ref(|value| assert_eq!(value, b"secret"));
+    a.with_mut(|value| value[0] = b'S');
+    a.with_ref(|value| assert_eq!(value, b"Secret"));
+  }
+
+  #[test]
+  fn grows_past_page() {
+    let mut a = SensitiveVec::new().unwrap();
+    a.extend_from_slice(&vec![1; page_size()]).unwrap();
+    assert_eq!(a.capacity(), page_size());
+    let old = a.buffer.inner_ptr as *mut u8 as usize;
+    a.push(2).unwrap();
+    assert!(freed_zeroized(old));
+    assert_eq!(a.len(), page_size() + 1);
+    assert_eq!(a.capacity(), 2 * page_size());
+    assert!(a.buffer.is_locked());
+    a.with_ref(|value| {
+       assert!(value[..page_size()].iter().all(|b| *b == 1));
+       assert_eq!(value[page_size()], 2);
+     });
+  }
+
+  #[test]
+  fn clear_zeroizes() {
+    let mut a = SensitiveVec::with_capacity(10).unwrap();
+    a.extend_from_slice(b"secret").unwrap();
+    a.clear();
+    assert!(a.is_empty());
+    assert_eq!(a.capacity(), page_size());
+    assert!(a.buffer.borrow().iter().all(|b| *b == 0));
+  }
+
+  #[test]
+  fn too_large() {
+    let mut a = SensitiveVec::new().unwrap();
+    a.push(1).unwrap();
+    assert!(matches!(a.reserve(usize::MAX), Err(Error::TooLarge)));
+  }
+}
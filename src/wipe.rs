@@ -0,0 +1,117 @@
+//! Overwriting the memory with random bytes before it's zeroized, for
+//! compliance regimes that ask for more than a single pass.
+
+use core::sync::atomic::{compiler_fence, fence, Ordering};
+#[cfg(test)]
+use std::cell::Cell;
+
+use super::zeroize;
+
+/// What's written over the memory of a `SensitiveData` before it's freed. The
+/// last pass is always zeroes, and every pass is written with volatile writes
+/// like `zeroize` does, so none of them can be optimized away.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WipePolicy {
+  /// A single pass of zeroes, which is what every `SensitiveData` gets.
+  #[default]
+  ZeroOnce,
+  /// A pass of random bytes, then one of zeroes.
+  RandomThenZero,
+  /// `n` passes in total, all of random bytes but the last. Less than two is
+  /// the same as `ZeroOnce`.
+  NPass(u32),
+}
+
+#[cfg(test)]
+type Rng = fn(&mut [u8]);
+
+#[cfg(test)]
+std::thread_local! {
+  /// Used instead of `getrandom`, to observe the passes.
+  static INJECTED_RNG: Cell<Option<Rng>> = const { Cell::new(None) };
+}
+
+fn fill(bytes: &mut [u8]) -> Result<(), getrandom::Error> {
+  #[cfg(test)]
+  if let Some(fill) = INJECTED_RNG.with(Cell::get) {
+    fill(bytes);
+    return Ok(());
+  }
+  getrandom::fill(bytes)
+}
+
+impl WipePolicy {
+  fn random_passes(self) -> u32 {
+    match self {
+      WipePolicy::ZeroOnce => 0,
+      WipePolicy::RandomThenZero => 1,
+      WipePolicy::NPass(n) => n.saturating_sub(1),
+    }
+  }
+
+  /// Runs the random passes over `bytes`, leaving the final pass of zeroes to
+  /// the caller. If no random bytes can be had, the remaining passes are
+  /// skipped, since the memory is zeroized either way.
+  pub(crate) fn overwrite(self, bytes: &mut [u8]) {
+    let mut random = [0u8; 64];
+    'passes: for _ in 0..self.random_passes() {
+      compiler_fence(Ordering::SeqCst);
+      for chunk in bytes.chunks_mut(random.len()) {
+        let random = &mut random[..chunk.len()];
+        if fill(random).is_err() {
+          break 'passes;
+        }
+        for (byte, random) in chunk.iter_mut().zip(random.iter()) {
+          unsafe { core::ptr::write_volatile(byte, *random) }
+        }
+      }
+      fence(Ordering::Release);
+    }
+    zeroize(&mut random);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{tests::freed_zeroized, SensitiveData};
+
+  std::thread_local! {
+    static RANDOM_BYTES: Cell<usize> = const { Cell::new(0) };
+  }
+
+  fn counting_rng(bytes: &mut [u8]) {
+    RANDOM_BYTES.with(|count| count.set(count.get() + bytes.len()));
+    bytes.fill(0xa5);
+  }
+
+  /// Returns how many pages of random bytes were written over a value with
+  /// `policy`, and checks that it was zeroized after all.
+  fn random_pages(policy: WipePolicy) -> usize {
+    INJECTED_RNG.with(|rng| rng.set(Some(counting_rng)));
+    RANDOM_BYTES.with(|count| count.set(0));
+    let a = SensitiveData::new_with_wipe_policy([1u8; 100], policy).unwrap();
+    let (address, size) = (a.inner_ptr as usize, a.memory_layout.size());
+    drop(a);
+    INJECTED_RNG.with(|rng| rng.set(None));
+    assert!(freed_zeroized(address));
+    RANDOM_BYTES.with(Cell::get) / size
+  }
+
+  #[test]
+  fn passes() {
+    assert_eq!(random_pages(WipePolicy::ZeroOnce), 0);
+    assert_eq!(random_pages(WipePolicy::RandomThenZero), 1);
+    assert_eq!(random_pages(WipePolicy::NPass(0)), 0);
+    assert_eq!(random_pages(WipePolicy::NPass(1)), 0);
+    assert_eq!(random_pages(WipePolicy::NPass(7)), 6);
+  }
+
+  #[test]
+  fn overwritten_with_random() {
+    let mut bytes = [0u8; 100];
+    WipePolicy::RandomThenZero.overwrite(&mut bytes);
+    // A hundred zeroes from a working RNG is beyond unlikely.
+    assert_ne!(bytes, [0; 100]);
+  }
+}